@@ -0,0 +1,46 @@
+use num_bigint::BigInt;
+
+fn mod_floor(value: &BigInt, modulus: &BigInt) -> BigInt {
+    ((value % modulus) + modulus) % modulus
+}
+
+fn extended_gcd(a: &BigInt, b: &BigInt) -> (BigInt, BigInt, BigInt) {
+    let (mut old_r, mut r) = (a.clone(), b.clone());
+    let (mut old_s, mut s) = (BigInt::from(1), BigInt::from(0));
+    let (mut old_t, mut t) = (BigInt::from(0), BigInt::from(1));
+    while r != BigInt::from(0) {
+        let quotient = &old_r / &r;
+        let next_r = &old_r - &quotient * &r;
+        old_r = std::mem::replace(&mut r, next_r);
+        let next_s = &old_s - &quotient * &s;
+        old_s = std::mem::replace(&mut s, next_s);
+        let next_t = &old_t - &quotient * &t;
+        old_t = std::mem::replace(&mut t, next_t);
+    }
+    (old_r, old_s, old_t)
+}
+
+fn combine(first: &(BigInt, BigInt), second: &(BigInt, BigInt)) -> Option<(BigInt, BigInt)> {
+    let (a1, m1) = first;
+    let (a2, m2) = second;
+    let (g, p, _q) = extended_gcd(m1, m2);
+    let difference = a2 - a1;
+    if &difference % &g != BigInt::from(0) {
+        return None;
+    }
+    let step = m2 / &g;
+    let lcm = m1 * &step;
+    let k = mod_floor(&(&difference / &g * p), &step);
+    Some((mod_floor(&(a1 + m1 * k), &lcm), lcm))
+}
+
+pub fn solve(congruences: &[(BigInt, BigInt)]) -> Option<(BigInt, BigInt)> {
+    let mut result = (BigInt::from(0), BigInt::from(1));
+    for (residue, modulus) in congruences {
+        if modulus <= &BigInt::from(0) {
+            return None;
+        }
+        result = combine(&result, &(mod_floor(residue, modulus), modulus.clone()))?;
+    }
+    Some(result)
+}
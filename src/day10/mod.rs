@@ -1,12 +1,193 @@
+use std::fmt;
 use std::fs::File;
 use std::io::{BufRead, BufReader};
-use super::Day;
+use std::collections::BTreeMap;
+use num_bigint::BigUint;
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
+use super::{Day, flag_value};
+
+const DEFAULT_JUMPS: [u64; 3] = [1, 2, 3];
+
+pub struct Count(BigUint);
+
+impl fmt::Debug for Count {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+fn count_arrangements(adapters: &[u64], jumps: &[u64]) -> BigUint {
+    AdapterChains::new(adapters, jumps).count().clone()
+}
+
+struct AdapterChains {
+    joltages: Vec<u64>,
+    successors: Vec<Vec<usize>>,
+    from_start: Vec<BigUint>,
+    to_end: Vec<BigUint>
+}
+
+impl AdapterChains {
+    fn new(adapters: &[u64], jumps: &[u64]) -> AdapterChains {
+        let mut joltages = adapters.to_vec();
+        joltages.push(0);
+        joltages.sort_unstable();
+        let max_jump = jumps.iter().max().cloned().unwrap_or(0);
+        let successors: Vec<Vec<usize>> = (0..joltages.len()).map(|i| {
+            (i + 1..joltages.len())
+                .take_while(|j| joltages[*j] - joltages[i] <= max_jump)
+                .filter(|j| jumps.contains(&(joltages[*j] - joltages[i])))
+                .collect()
+        }).collect();
+        let last = joltages[joltages.len() - 1];
+        let mut to_end: Vec<BigUint> = vec![BigUint::from(0u32); joltages.len()];
+        for i in (0..joltages.len()).rev() {
+            to_end[i] = if joltages[i] == last {
+                BigUint::from(1u32)
+            } else {
+                successors[i].iter().fold(BigUint::from(0u32), |acc, j| acc + &to_end[*j])
+            };
+        }
+        let mut from_start: Vec<BigUint> = vec![BigUint::from(0u32); joltages.len()];
+        from_start[0] = BigUint::from(1u32);
+        for i in 0..joltages.len() {
+            for j in &successors[i] {
+                from_start[*j] = &from_start[*j] + &from_start[i];
+            }
+        }
+        AdapterChains { joltages, successors, from_start, to_end }
+    }
+
+    fn count(&self) -> &BigUint {
+        &self.to_end[0]
+    }
+
+    fn chains(&self) -> ChainIterator<'_> {
+        let stack = if self.to_end[0] > BigUint::from(0u32) { vec![(0, 0)] } else { vec![] };
+        ChainIterator { chains: self, stack }
+    }
+
+    fn sample<R: Rng>(&self, rng: &mut R) -> Option<Vec<u64>> {
+        if self.count() == &BigUint::from(0u32) {
+            return None;
+        }
+        let last = self.joltages[self.joltages.len() - 1];
+        let mut current = 0;
+        let mut chain = vec![];
+        while self.joltages[current] != last {
+            let mut pick = random_below(rng, &self.to_end[current]);
+            for next in &self.successors[current] {
+                if pick < self.to_end[*next] {
+                    current = *next;
+                    break;
+                }
+                pick -= &self.to_end[*next];
+            }
+            chain.push(self.joltages[current]);
+        }
+        Some(chain)
+    }
+
+    fn mandatory(&self) -> Vec<u64> {
+        let total = self.count();
+        if total == &BigUint::from(0u32) {
+            return vec![];
+        }
+        (1..self.joltages.len())
+            .filter(|i| &(&self.from_start[*i] * &self.to_end[*i]) == total)
+            .map(|i| self.joltages[i])
+            .collect()
+    }
+}
+
+struct ChainIterator<'a> {
+    chains: &'a AdapterChains,
+    stack: Vec<(usize, usize)>
+}
+
+impl<'a> Iterator for ChainIterator<'a> {
+    type Item = Vec<u64>;
+
+    fn next(&mut self) -> Option<Vec<u64>> {
+        let last = self.chains.joltages[self.chains.joltages.len() - 1];
+        while let Some((index, next)) = self.stack.pop() {
+            if self.chains.joltages[index] == last {
+                return Some(self.stack.iter().skip(1).map(|(i, _)| self.chains.joltages[*i]).chain(std::iter::once(last)).collect());
+            }
+            let successors = &self.chains.successors[index];
+            if let Some(pos) = (next..successors.len()).find(|pos| self.chains.to_end[successors[*pos]] > BigUint::from(0u32)) {
+                self.stack.push((index, pos + 1));
+                self.stack.push((successors[pos], 0));
+            }
+        }
+        None
+    }
+}
+
+fn random_below<R: Rng>(rng: &mut R, bound: &BigUint) -> BigUint {
+    let bits = bound.bits();
+    let mut bytes = vec![0u8; bits.div_ceil(8) as usize];
+    loop {
+        rng.fill_bytes(&mut bytes);
+        let spare = bits % 8;
+        if spare != 0 {
+            let last = bytes.len() - 1;
+            bytes[last] &= (1u8 << spare) - 1;
+        }
+        let value = BigUint::from_bytes_le(&bytes);
+        if &value < bound {
+            return value;
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct GapReport {
+    differences: BTreeMap<u64, u64>,
+    impossible: Vec<(u64, u64)>
+}
+
+impl fmt::Display for GapReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (difference, count) in &self.differences {
+            writeln!(f, "{} jumps of {}", count, difference)?;
+        }
+        for (from, to) in &self.impossible {
+            writeln!(f, "impossible gap: {} -> {}", from, to)?;
+        }
+        Ok(())
+    }
+}
+
+fn validate_gaps(adapters: &[u64], jumps: &[u64]) -> GapReport {
+    let mut list = adapters.to_vec();
+    list.sort_unstable();
+    list.insert(0, 0);
+    list.push(list[list.len() - 1] + 3);
+    let mut report = GapReport::default();
+    for (from, to) in list.iter().zip(list.iter().skip(1)) {
+        let difference = to - from;
+        *report.differences.entry(difference).or_insert(0) += 1;
+        if !jumps.contains(&difference) {
+            report.impossible.push((*from, *to));
+        }
+    }
+    report
+}
+
+fn jumps_from(args: &[String]) -> Vec<u64> {
+    match flag_value(args, "--jumps") {
+        Some(jumps) => jumps.split(',').map(|j| j.trim().parse::<u64>().expect("Jump must be integer")).collect(),
+        None => DEFAULT_JUMPS.to_vec()
+    }
+}
 
 pub struct Day10 {}
 
 impl Day for Day10 { 
     type Input = Vec<u64>;
-    type Output = u64;
+    type Output = Count;
 
     fn read() -> Vec<u64> {
         let mut data: Vec<u64> = vec![];
@@ -19,50 +200,54 @@ impl Day for Day10 {
         data
     }
 
-    fn part1(input: &Vec<u64>) -> u64 {
-        let mut ones = 0;
-        let mut threes = 0;
-        let mut list = input.clone();
-        let mut joltage = 0;
-        list.sort();
-        while !list.is_empty() {
-            let diff = list.first().expect("List cannot be empty") - joltage;
-            match diff {
-                1 => ones += 1,
-                2 => (),
-                3 => threes += 1,
-                _ => panic!("Invalid joltage jump!")
-            } 
-            joltage += diff;
-            list.remove(0);
+    fn part1(input: &Vec<u64>) -> Count {
+        let report = validate_gaps(input, &DEFAULT_JUMPS);
+        if !report.impossible.is_empty() {
+            print!("{}", report);
         }
-        threes += 1;
+        let ones = report.differences.get(&1).cloned().unwrap_or(0);
+        let threes = report.differences.get(&3).cloned().unwrap_or(0);
         println!("{:?} {:?}", ones, threes);
-        ones * threes
+        Count(BigUint::from(ones * threes))
+    }
+
+    fn part2(input: &Vec<u64>) -> Count {
+        Count(count_arrangements(input, &DEFAULT_JUMPS))
     }
 
-    fn part2(input: &Vec<u64>) -> u64 {
-        let run_combinations: Vec<u64> = vec![0, 1, 2, 4, 7, 13, 24];
-        let mut list = input.clone();
-        list.sort();
-        list.insert(0, 0);
-        let diffs: Vec<u64> = list.iter().zip(list.iter().skip(1)).map(|(cur, next)| next - cur).collect();
-        let mut run_values: Vec<u64> = vec![0, 0, 0, 0, 0, 0, 0];
-        let mut ones = 0;
-        for (i, diff) in diffs.iter().enumerate() {
-            if *diff == 1 {
-                ones += 1;
-                if i == diffs.len() - 1 {
-                    run_values[ones] += 1;
-                    ones = 0;
+    fn command(name: &str, args: &[String]) {
+        let jumps = jumps_from(args);
+        match name {
+            "count" => println!("{}", count_arrangements(&Self::read(), &jumps)),
+            "validate" => {
+                let report = validate_gaps(&Self::read(), &jumps);
+                print!("{}", report);
+                println!("{}", if report.impossible.is_empty() { "Chain is valid" } else { "Chain is invalid" });
+            },
+            "chains" => {
+                let limit = flag_value(args, "--limit").map(|l| l.parse::<usize>().expect("Limit must be integer")).unwrap_or(10);
+                let chains = AdapterChains::new(&Self::read(), &jumps);
+                for chain in chains.chains().take(limit) {
+                    println!("{:?}", chain);
                 }
-            } else if ones > 0 {
-                run_values[ones] += 1;
-                ones = 0;
-            }
+                println!("{} chains in total", chains.count());
+            },
+            "sample" => {
+                let chains = AdapterChains::new(&Self::read(), &jumps);
+                let mut rng: StdRng = match flag_value(args, "--seed") {
+                    Some(seed) => StdRng::seed_from_u64(seed.parse::<u64>().expect("Seed must be integer")),
+                    None => StdRng::from_entropy()
+                };
+                match chains.sample(&mut rng) {
+                    Some(chain) => println!("{:?}", chain),
+                    None => println!("No valid chain exists")
+                }
+            },
+            "mandatory" => {
+                let chains = AdapterChains::new(&Self::read(), &jumps);
+                println!("{:?}", chains.mandatory());
+            },
+            _ => println!("Unknown command: {:?}", name)
         }
-        let combinations: Vec<u64> = run_values.iter().enumerate().map(|(i, s)| run_combinations[i].pow(*s as u32)).collect();
-        let combined = combinations.iter().fold(1, |acc, x| acc * x);
-        combined
     }
 }
\ No newline at end of file
@@ -1,7 +1,12 @@
+use std::borrow::Cow;
+use std::convert::TryFrom;
 use std::fmt;
-use std::fs::File;
-use std::io::{BufRead, BufReader};
-use super::Day;
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+use std::rc::Rc;
+use std::time::Duration;
+use super::{Day, flag_value};
 
 #[derive(Clone)]
 pub struct SeatLayout {
@@ -9,216 +14,350 @@ pub struct SeatLayout {
 }
 
 impl SeatLayout {
-    fn iterate(&mut self) -> bool {
-        let mut next = self.cells.clone();
-        let mut changed = false;
-        let last_index = self.cells.len() - 1;
-        for row in 0..=last_index {
-            let last_index = self.cells[row].len() - 1;
-            for col in 0..=last_index {
-                let occupied_count = self.occupied_count_near(col, row);
-                if self.is_empty_at(col, row) && occupied_count == 0 {
-                    next[row][col] = Cell::Occupied;
-                    changed = true;
-                }
-                if self.is_occupied_at(col, row) && occupied_count >= 4 {
-                    next[row][col] = Cell::Empty;
-                    changed = true;
-                }
-            }
-        }
-        if changed {
-            self.cells = next;
-        }
-        changed
+    fn automaton(&self, neighborhood: Box<dyn Neighborhood<Cell>>, threshold: u32) -> Automaton<Cell> {
+        let rule = ThresholdRule {
+            counted: Cell::Occupied,
+            transitions: vec![
+                Transition { from: Cell::Empty, to: Cell::Occupied, min: 0, max: 0 },
+                Transition { from: Cell::Occupied, to: Cell::Empty, min: threshold, max: u32::MAX }
+            ]
+        };
+        let width = self.cells.first().map(|row| row.len()).unwrap_or(0);
+        let cells: Vec<Cell> = self.cells.iter().flat_map(|row| row.iter().cloned()).collect();
+        Automaton::new(width, cells, neighborhood, Box::new(rule))
     }
-    fn occupied_cells_count(&self) -> u64 {
-        let mut count = 0;
-        let last_index = self.cells.len() - 1;
-        for row in 0..=last_index {
-            let last_index = self.cells[row].len() - 1;
-            for col in 0..=last_index {
-                if self.is_occupied_at(col, row) {
-                    count += 1;
-                }
-            }
-        }
-        count
+}
+
+pub trait Neighborhood<C> {
+    fn neighbors(&self, cells: &[C], width: usize, x: usize, y: usize) -> Vec<usize>;
+    fn preserves(&self, _from: &C, _to: &C) -> bool {
+        true
+    }
+}
+
+const ALL_DIRECTIONS: [(i32, i32); 8] = [(-1, -1), (0, -1), (1, -1), (-1, 0), (1, 0), (-1, 1), (0, 1), (1, 1)];
+const ORTHOGONAL_DIRECTIONS: [(i32, i32); 4] = [(0, -1), (-1, 0), (1, 0), (0, 1)];
+
+fn offset(cells_len: usize, width: usize, x: usize, y: usize, x_dir: i32, y_dir: i32) -> Option<(usize, usize)> {
+    let height = cells_len / width;
+    let target_x = x as i32 + x_dir;
+    let target_y = y as i32 + y_dir;
+    if target_x < 0 || target_y < 0 || target_x >= width as i32 || target_y >= height as i32 {
+        None
+    } else {
+        Some((target_x as usize, target_y as usize))
     }
-    fn cell_at(&self, x: usize, y: usize) -> Option<&Cell> {
-        if let Some(row) = self.cells.get(y) {
-            if let Some(cell) = row.get(x) {
-                return Some(cell);
+}
+
+pub struct Adjacent;
+
+impl<C> Neighborhood<C> for Adjacent {
+    fn neighbors(&self, cells: &[C], width: usize, x: usize, y: usize) -> Vec<usize> {
+        ALL_DIRECTIONS.iter()
+            .filter_map(|(x_dir, y_dir)| offset(cells.len(), width, x, y, *x_dir, *y_dir))
+            .map(|(x, y)| y * width + x)
+            .collect()
+    }
+}
+
+pub struct VonNeumann;
+
+impl<C> Neighborhood<C> for VonNeumann {
+    fn neighbors(&self, cells: &[C], width: usize, x: usize, y: usize) -> Vec<usize> {
+        ORTHOGONAL_DIRECTIONS.iter()
+            .filter_map(|(x_dir, y_dir)| offset(cells.len(), width, x, y, *x_dir, *y_dir))
+            .map(|(x, y)| y * width + x)
+            .collect()
+    }
+}
+
+pub struct LineOfSight<C> {
+    transparent: C
+}
+
+impl<C: PartialEq> LineOfSight<C> {
+    fn first_seat_in(&self, cells: &[C], width: usize, x: usize, y: usize, x_dir: i32, y_dir: i32) -> Option<usize> {
+        let mut current = (x, y);
+        while let Some((target_x, target_y)) = offset(cells.len(), width, current.0, current.1, x_dir, y_dir) {
+            let index = target_y * width + target_x;
+            if cells[index] != self.transparent {
+                return Some(index);
             }
+            current = (target_x, target_y);
         }
         None
     }
-    fn is_occupied_at(&self, x: usize, y: usize) -> bool {
-        if let Some(cell) = self.cell_at(x, y) {
-            return *cell == Cell::Occupied;
-        }
-        false
+}
+
+impl<C: PartialEq> Neighborhood<C> for LineOfSight<C> {
+    fn neighbors(&self, cells: &[C], width: usize, x: usize, y: usize) -> Vec<usize> {
+        ALL_DIRECTIONS.iter()
+            .filter_map(|(x_dir, y_dir)| self.first_seat_in(cells, width, x, y, *x_dir, *y_dir))
+            .collect()
     }
-    fn is_empty_at(&self, x: usize, y: usize) -> bool {
-        if let Some(cell) = self.cell_at(x, y) {
-            return *cell == Cell::Empty;
-        }
-        false
+
+    // Sight lines are computed once from the initial cells, so no cell may become or stop being transparent.
+    fn preserves(&self, from: &C, to: &C) -> bool {
+        *from != self.transparent && *to != self.transparent
     }
-    fn occupied_count_near(&self, x: usize, y: usize) -> u32 {
-        let mut occupied = 0;
-        if y > 0 { 
-            if let Some(top_row) = self.cells.get(y - 1) {
-                if x > 0 {
-                    if let Some(top_left_cell) = top_row.get(x - 1) {
-                        if *top_left_cell == Cell::Occupied {
-                            occupied += 1;
-                        }
-                    }
-                }
-                if let Some(top_middle_cell) = top_row.get(x) {
-                    if *top_middle_cell == Cell::Occupied {
-                        occupied += 1;
-                    }
-                }
-                if x < usize::MAX {
-                    if let Some(top_right_cell) = top_row.get(x + 1) {
-                        if *top_right_cell == Cell::Occupied {
-                            occupied += 1;
-                        }
-                    }
-                }
+}
+
+pub trait Rule<C> {
+    fn next(&self, cell: &C, neighbors: &[&C]) -> C;
+}
+
+pub struct Transition<C> {
+    from: C,
+    to: C,
+    min: u32,
+    max: u32
+}
+
+pub struct ThresholdRule<C> {
+    counted: C,
+    transitions: Vec<Transition<C>>
+}
+
+impl<C: Clone + PartialEq> Rule<C> for ThresholdRule<C> {
+    fn next(&self, cell: &C, neighbors: &[&C]) -> C {
+        let count = neighbors.iter().filter(|n| ***n == self.counted).count() as u32;
+        for transition in &self.transitions {
+            if *cell == transition.from && count >= transition.min && count <= transition.max {
+                return transition.to.clone();
             }
         }
-        if let Some(current_row) = self.cells.get(y) {
-            if x > 0 {
-                if let Some(left_cell) = current_row.get(x - 1) {
-                    if *left_cell == Cell::Occupied {
-                        occupied += 1;
-                    }
-                }
-            }
-            if x < usize::MAX {
-                if let Some(right_cell) = current_row.get(x + 1) {
-                    if *right_cell == Cell::Occupied {
-                        occupied += 1;
-                    }
-                }
+        cell.clone()
+    }
+}
+
+#[derive(Clone)]
+pub struct Automaton<C> {
+    width: usize,
+    cells: Vec<C>,
+    neighbors: Rc<Vec<Vec<usize>>>,
+    watchers: Rc<Vec<Vec<usize>>>,
+    dirty: Vec<usize>,
+    queued: Vec<bool>,
+    neighborhood: Rc<dyn Neighborhood<C>>,
+    rule: Rc<dyn Rule<C>>
+}
+
+impl<C: Clone + PartialEq> Automaton<C> {
+    fn new(width: usize, cells: Vec<C>, neighborhood: Box<dyn Neighborhood<C>>, rule: Box<dyn Rule<C>>) -> Automaton<C> {
+        let neighbors: Vec<Vec<usize>> = (0..cells.len())
+            .map(|index| neighborhood.neighbors(&cells, width, index % width, index / width))
+            .collect();
+        let mut watchers: Vec<Vec<usize>> = vec![vec![]; cells.len()];
+        for (index, list) in neighbors.iter().enumerate() {
+            for neighbor in list {
+                watchers[*neighbor].push(index);
             }
         }
-        if y < usize::MAX { 
-            if let Some(bottom_row) = self.cells.get(y + 1) {
-                if x > 0 {
-                    if let Some(bottom_left_cell) = bottom_row.get(x - 1) {
-                        if *bottom_left_cell == Cell::Occupied {
-                            occupied += 1;
-                        }
-                    }
-                }
-                if let Some(bottom_middle_cell) = bottom_row.get(x) {
-                    if *bottom_middle_cell == Cell::Occupied {
-                        occupied += 1;
-                    }
-                }
-                if x < usize::MAX {
-                    if let Some(bottom_right_cell) = bottom_row.get(x + 1) {
-                        if *bottom_right_cell == Cell::Occupied {
-                            occupied += 1;
-                        }
-                    }
-                }
-            }
+        let dirty = (0..cells.len()).collect();
+        let queued = vec![false; cells.len()];
+        Automaton {
+            width,
+            cells,
+            neighbors: Rc::new(neighbors),
+            watchers: Rc::new(watchers),
+            dirty,
+            queued,
+            neighborhood: Rc::from(neighborhood),
+            rule: Rc::from(rule)
         }
-        occupied
     }
-    
-    fn iterate_part2(&mut self) -> bool {
-        let mut next = self.cells.clone();
-        let mut changed = false;
-        let last_index = self.cells.len() - 1;
-        for row in 0..=last_index {
-            let last_index = self.cells[row].len() - 1;
-            for col in 0..=last_index {
-                let occupied_count = self.occupied_count_near_part2(col, row);
-                if self.is_empty_at(col, row) && occupied_count == 0 {
-                    next[row][col] = Cell::Occupied;
-                    changed = true;
-                }
-                if self.is_occupied_at(col, row) && occupied_count >= 5 {
-                    next[row][col] = Cell::Empty;
-                    changed = true;
+
+    fn step(&mut self) -> bool {
+        let changes: Vec<(usize, C)> = self.dirty.iter().filter_map(|index| {
+            let cell = &self.cells[*index];
+            let neighbors: Vec<&C> = self.neighbors[*index].iter().map(|n| &self.cells[*n]).collect();
+            let next = self.rule.next(cell, &neighbors);
+            if next != *cell {
+                Some((*index, next))
+            } else {
+                None
+            }
+        }).collect();
+        self.dirty.clear();
+        for (index, next) in &changes {
+            assert!(self.neighborhood.preserves(&self.cells[*index], next), "Rule changed a cell the neighborhood depends on");
+            self.cells[*index] = next.clone();
+            for affected in self.watchers[*index].iter().chain(std::iter::once(index)) {
+                if !self.queued[*affected] {
+                    self.queued[*affected] = true;
+                    self.dirty.push(*affected);
                 }
             }
         }
-        if changed {
-            self.cells = next;
+        for index in &self.dirty {
+            self.queued[*index] = false;
         }
-        changed
+        !changes.is_empty()
     }
-    fn first_seat_in(&self, current_x: usize, current_y: usize, x_dir: i32, y_dir: i32) -> Option<&Cell> {
-        let current_x = current_x as i32;
-        let current_y = current_y as i32;
-        let max_x = (self.cells[0].len() - 1) as i32;
-        let max_y = (self.cells.len() - 1) as i32;
-        let min_x = 0;
-        let min_y = 0;
-        let mut target_x = current_x + x_dir;
-        let mut target_y = current_y + y_dir;
-        while target_x >= min_x && target_x <= max_x && target_y >= min_y && target_y <= max_y {
-            if let Some(cell) = self.cell_at(target_x as usize, target_y as usize) {
-                if *cell == Cell::Occupied || *cell == Cell::Empty {
-                    return Some(cell);
-                }
-            }
-            target_x += x_dir;
-            target_y += y_dir;
-        }
-        None
+
+    fn count(&self, target: &C) -> u64 {
+        self.cells.iter().filter(|cell| *cell == target).count() as u64
     }
-    fn occupied_count_near_part2(&self, x: usize, y: usize) -> u32 {
-        let mut occupied = 0;
-        if let Some(top_left_cell) = self.first_seat_in(x, y, -1, -1) {
-            if *top_left_cell == Cell::Occupied {
-                occupied += 1;
+}
+
+impl<C: Clone + PartialEq> Automaton<C> {
+    fn run<F: FnMut(&Automaton<C>)>(&mut self, max_generations: usize, mut observe: F) -> Settlement {
+        let initial = self.clone();
+        let mut saved = self.cells.clone();
+        let mut power = 1;
+        let mut period = 0;
+        observe(self);
+        for generation in 1..=max_generations {
+            if !self.step() {
+                return Settlement::Stable { generation: generation - 1 };
             }
-        }
-        if let Some(top_middle_cell) = self.first_seat_in(x, y, 0, -1) {
-            if *top_middle_cell == Cell::Occupied {
-                occupied += 1;
+            observe(self);
+            period += 1;
+            if self.cells == saved {
+                let (start, at_start) = initial.cycle_start(period);
+                *self = at_start;
+                return Settlement::Cycle { start, period };
             }
-        }
-        if let Some(top_right_cell) = self.first_seat_in(x, y, 1, -1) {
-            if *top_right_cell == Cell::Occupied {
-                occupied += 1;
+            if period == power {
+                saved = self.cells.clone();
+                power *= 2;
+                period = 0;
             }
         }
-        if let Some(left_cell) = self.first_seat_in(x, y, -1, 0) {
-            if *left_cell == Cell::Occupied {
-                occupied += 1;
-            }
+        Settlement::Capped { generations: max_generations }
+    }
+
+    fn cycle_start(mut self, period: usize) -> (usize, Automaton<C>) {
+        let mut ahead = self.clone();
+        for _ in 0..period {
+            ahead.step();
         }
-        if let Some(right_cell) = self.first_seat_in(x, y, 1, 0) {
-            if *right_cell == Cell::Occupied {
-                occupied += 1;
-            }
+        let mut start = 0;
+        while self.cells != ahead.cells {
+            self.step();
+            ahead.step();
+            start += 1;
         }
-        if let Some(bottom_left_cell) = self.first_seat_in(x, y, -1, 1) {
-            if *bottom_left_cell == Cell::Occupied {
-                occupied += 1;
-            }
+        (start, self)
+    }
+}
+
+const MAX_GENERATIONS: usize = 10_000;
+
+#[derive(Debug, PartialEq)]
+pub enum Settlement {
+    Stable { generation: usize },
+    Cycle { start: usize, period: usize },
+    Capped { generations: usize }
+}
+
+impl fmt::Display for Settlement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Settlement::Stable { generation } => write!(f, "stable after {} generations", generation),
+            Settlement::Cycle { start, period } => write!(f, "enters period-{} cycle at generation {}", period, start),
+            Settlement::Capped { generations } => write!(f, "not settled after {} generations", generations)
         }
-        if let Some(bottom_middle_cell) = self.first_seat_in(x, y, 0, 1) {
-            if *bottom_middle_cell == Cell::Occupied {
-                occupied += 1;
+    }
+}
+
+impl Automaton<Cell> {
+    fn layout(&self) -> SeatLayout {
+        SeatLayout { cells: self.cells.chunks(self.width).map(|row| row.to_vec()).collect() }
+    }
+}
+
+fn record(automaton: &mut Automaton<Cell>, max_generations: usize) -> (Vec<SeatLayout>, Settlement) {
+    let mut frames = vec![];
+    let settlement = automaton.run(max_generations, |a| frames.push(a.layout()));
+    (frames, settlement)
+}
+
+fn play(frames: &[SeatLayout], delay: Duration) {
+    for (generation, frame) in frames.iter().enumerate() {
+        let mut screen = String::from("\x1b[2J\x1b[H");
+        for row in &frame.cells {
+            for cell in row {
+                let color = match cell {
+                    Cell::Floor => "\x1b[90m",
+                    Cell::Empty => "\x1b[32m",
+                    Cell::Occupied => "\x1b[31m"
+                };
+                screen.push_str(&format!("{}{}", color, cell));
             }
+            screen.push_str("\x1b[0m\n");
+        }
+        screen.push_str(&format!("generation {}\n", generation));
+        print!("{}", screen);
+        std::io::stdout().flush().expect("Stdout must flush");
+        std::thread::sleep(delay);
+    }
+}
+
+fn write_text_frames(frames: &[SeatLayout], directory: &str) {
+    fs::create_dir_all(directory).expect("Frame directory must be creatable");
+    for entry in fs::read_dir(directory).expect("Frame directory must be readable") {
+        let path = entry.expect("Frame directory entry must be readable").path();
+        let name = path.file_name().and_then(|name| name.to_str()).unwrap_or("");
+        if name.starts_with("frame_") && name.ends_with(".txt") {
+            fs::remove_file(&path).expect("Old frame file must be removable");
         }
-        if let Some(bottom_right_cell) = self.first_seat_in(x, y, 1, 1) {
-            if *bottom_right_cell == Cell::Occupied {
-                occupied += 1;
+    }
+    for (generation, frame) in frames.iter().enumerate() {
+        let path = Path::new(directory).join(format!("frame_{:04}.txt", generation));
+        fs::write(path, frame.to_string().trim_start()).expect("Frame file must be writable");
+    }
+}
+
+fn write_gif(frames: &[SeatLayout], path: &str, scale: usize, delay: Duration) -> Result<(), String> {
+    let height = frames[0].cells.len();
+    let width = frames[0].cells.first().map(|row| row.len()).unwrap_or(0);
+    let dimension = |size: usize| u16::try_from(size * scale).map_err(|_| format!("Gif dimension {} x {} exceeds {}", size, scale, u16::MAX));
+    let (gif_width, gif_height) = (dimension(width)?, dimension(height)?);
+    let palette: [u8; 9] = [40, 40, 40, 60, 180, 75, 220, 50, 50];
+    let file = File::create(path).expect("Gif file must be writable");
+    let mut encoder = gif::Encoder::new(file, gif_width, gif_height, &palette).expect("Gif encoder must start");
+    encoder.set_repeat(gif::Repeat::Infinite).expect("Gif must loop");
+    for frame in frames {
+        let mut pixels: Vec<u8> = Vec::with_capacity(width * height * scale * scale);
+        for row in &frame.cells {
+            let line: Vec<u8> = row.iter().flat_map(|cell| {
+                let index = match cell {
+                    Cell::Floor => 0,
+                    Cell::Empty => 1,
+                    Cell::Occupied => 2
+                };
+                (0..scale).map(move |_| index)
+            }).collect();
+            for _ in 0..scale {
+                pixels.extend_from_slice(&line);
             }
         }
-        occupied
+        let image = gif::Frame {
+            width: gif_width,
+            height: gif_height,
+            buffer: Cow::Owned(pixels),
+            delay: (delay.as_millis() / 10) as u16,
+            ..gif::Frame::default()
+        };
+        encoder.write_frame(&image).expect("Gif frame must be writable");
     }
+    Ok(())
+}
+
+fn automaton_from(args: &[String]) -> Automaton<Cell> {
+    let neighborhood: Box<dyn Neighborhood<Cell>> = match flag_value(args, "--neighborhood").as_deref() {
+        None | Some("adjacent") => Box::new(Adjacent),
+        Some("sight") => Box::new(LineOfSight { transparent: Cell::Floor }),
+        Some("von-neumann") => Box::new(VonNeumann),
+        Some(other) => panic!("Unknown neighborhood: {:?}", other)
+    };
+    let threshold = flag_value(args, "--threshold").map(|t| t.parse::<u32>().expect("Threshold must be integer")).unwrap_or(4);
+    Day11::read().automaton(neighborhood, threshold)
+}
+
+fn max_generations_from(args: &[String]) -> usize {
+    flag_value(args, "--max-generations").map(|m| m.parse::<usize>().expect("Max generations must be integer")).unwrap_or(MAX_GENERATIONS)
 }
 
 impl fmt::Display for SeatLayout {
@@ -234,22 +373,13 @@ impl fmt::Display for SeatLayout {
     }
 }
 
-#[derive(Clone)]
+#[derive(Clone, PartialEq)]
 pub enum Cell {
     Floor,
     Empty,
     Occupied
 }
 
-impl PartialEq for Cell {
-    fn eq(&self, other: &Self) -> bool {
-        match (self, other) {
-            (Cell::Empty, Cell::Empty) | (Cell::Occupied, Cell::Occupied) | (Cell::Floor, Cell::Floor) => true,
-            _ => false
-        }
-    }
-}
-
 impl fmt::Display for Cell {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let c = match self {
@@ -288,14 +418,44 @@ impl Day for Day11 {
     }
 
     fn part1(input: &SeatLayout) -> u64 {
-        let mut layout = input.clone();
-        while layout.iterate() {}
-        layout.occupied_cells_count()
+        let mut automaton = input.automaton(Box::new(Adjacent), 4);
+        match automaton.run(MAX_GENERATIONS, |_| ()) {
+            Settlement::Stable { .. } => automaton.count(&Cell::Occupied),
+            settlement => panic!("Seats did not stabilize: {}", settlement)
+        }
     }
 
     fn part2(input: &SeatLayout) -> u64 {
-        let mut layout = input.clone();
-        while layout.iterate_part2() {}
-        layout.occupied_cells_count()
+        let mut automaton = input.automaton(Box::new(LineOfSight { transparent: Cell::Floor }), 5);
+        match automaton.run(MAX_GENERATIONS, |_| ()) {
+            Settlement::Stable { .. } => automaton.count(&Cell::Occupied),
+            settlement => panic!("Seats did not stabilize: {}", settlement)
+        }
+    }
+
+    fn command(name: &str, args: &[String]) {
+        match name {
+            "simulate" => {
+                let mut automaton = automaton_from(args);
+                let settlement = automaton.run(max_generations_from(args), |_| ());
+                println!("{} occupied, {}", automaton.count(&Cell::Occupied), settlement);
+            },
+            "animate" => {
+                let (frames, settlement) = record(&mut automaton_from(args), max_generations_from(args));
+                let delay = Duration::from_millis(flag_value(args, "--delay").map(|d| d.parse::<u64>().expect("Delay must be integer")).unwrap_or(100));
+                if let Some(directory) = flag_value(args, "--frames") {
+                    write_text_frames(&frames, &directory);
+                } else if let Some(path) = flag_value(args, "--gif") {
+                    let scale = flag_value(args, "--scale").map(|s| s.parse::<usize>().expect("Scale must be integer")).unwrap_or(4);
+                    if let Err(message) = write_gif(&frames, &path, scale, delay) {
+                        println!("{}", message);
+                    }
+                } else {
+                    play(&frames, delay);
+                }
+                println!("{}", settlement);
+            },
+            _ => println!("Unknown command: {:?}", name)
+        }
     }
 }
\ No newline at end of file
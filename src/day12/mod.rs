@@ -1,18 +1,53 @@
-use std::fs::File;
+use std::fmt;
+use std::fs::{self, File};
 use std::io::{BufRead, BufReader};
-use super::Day;
+use super::{Day, flag_value};
 
-fn rotate(x: i32, y: i32, deg: i32) -> (i32, i32) {
-    let frad = f64::to_radians(deg as f64);
-    let cos = f64::cos(frad) as i32;
-    let sin = f64::sin(frad) as i32;
-    ( 
-        cos * x - sin * y,
-        sin * x + cos * y
-    )
+#[derive(Debug)]
+pub enum NavigationError {
+    InvalidAngle(i32)
 }
 
-#[derive(Debug)]
+impl fmt::Display for NavigationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NavigationError::InvalidAngle(deg) => write!(f, "cannot rotate by {} degrees", deg)
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Compass {
+    Four,
+    Eight
+}
+
+const HEADINGS: [(i32, i32); 8] = [(1, 0), (1, 1), (0, 1), (-1, 1), (-1, 0), (-1, -1), (0, -1), (1, -1)];
+
+fn rotate(x: i32, y: i32, deg: i32) -> Result<(i32, i32), NavigationError> {
+    if deg % 90 != 0 {
+        return Err(NavigationError::InvalidAngle(deg));
+    }
+    Ok(match (deg / 90).rem_euclid(4) {
+        0 => (x, y),
+        1 => (-y, x),
+        2 => (-x, -y),
+        _ => (y, -x)
+    })
+}
+
+fn rotate_heading(x: i32, y: i32, deg: i32, compass: Compass) -> Result<(i32, i32), NavigationError> {
+    if compass == Compass::Four || deg % 90 == 0 {
+        return rotate(x, y, deg);
+    }
+    if deg % 45 != 0 {
+        return Err(NavigationError::InvalidAngle(deg));
+    }
+    let index = HEADINGS.iter().position(|h| *h == (x, y)).expect("Heading must be a compass direction");
+    Ok(HEADINGS[(index as i32 + deg / 45).rem_euclid(8) as usize])
+}
+
+#[derive(Debug, Clone)]
 pub enum Instruction {
     N(u32),
     E(u32),
@@ -23,37 +58,153 @@ pub enum Instruction {
     R(u32)
 }
 
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Instruction::N(num) => write!(f, "N{}", num),
+            Instruction::E(num) => write!(f, "E{}", num),
+            Instruction::W(num) => write!(f, "W{}", num),
+            Instruction::S(num) => write!(f, "S{}", num),
+            Instruction::F(num) => write!(f, "F{}", num),
+            Instruction::L(num) => write!(f, "L{}", num),
+            Instruction::R(num) => write!(f, "R{}", num)
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ShipState {
+    x: i32,
+    y: i32,
+    vx: i32,
+    vy: i32
+}
+
 trait Ship {
+    const VECTOR: &'static str;
     fn create() -> Self;
-    fn perform(&mut self, instruction: &Instruction);
+    fn perform(&mut self, instruction: &Instruction) -> Result<(), NavigationError>;
     fn distance_from(&self, x: i32, y: i32) -> i32;
+    fn state(&self) -> ShipState;
+    fn homing(&self) -> Vec<Instruction>;
 }
 
-#[derive(Debug)]
+fn shift(dx: i32, dy: i32) -> Vec<Instruction> {
+    let mut instructions = vec![];
+    if dx > 0 {
+        instructions.push(Instruction::E(dx as u32));
+    } else if dx < 0 {
+        instructions.push(Instruction::W(-dx as u32));
+    }
+    if dy > 0 {
+        instructions.push(Instruction::S(dy as u32));
+    } else if dy < 0 {
+        instructions.push(Instruction::N(-dy as u32));
+    }
+    instructions
+}
+
+fn turn(deg: i32) -> Vec<Instruction> {
+    match deg.rem_euclid(360) {
+        0 => vec![],
+        deg if deg <= 180 => vec![Instruction::R(deg as u32)],
+        deg => vec![Instruction::L((360 - deg) as u32)]
+    }
+}
+
+fn gcd(a: i32, b: i32) -> i32 {
+    if b == 0 { a } else { gcd(b, a % b) }
+}
+
+fn inverse<S: Ship>(mut ship: S, instructions: &[Instruction]) -> Result<Vec<Instruction>, NavigationError> {
+    navigate(&mut ship, instructions)?;
+    Ok(ship.homing())
+}
+
+fn simplify(instructions: &[Instruction]) -> Vec<Instruction> {
+    let mut simplified = vec![];
+    let mut i = 0;
+    while i < instructions.len() {
+        match instructions[i] {
+            Instruction::N(_) | Instruction::S(_) | Instruction::E(_) | Instruction::W(_) => {
+                let (mut dx, mut dy) = (0, 0);
+                while i < instructions.len() {
+                    match instructions[i] {
+                        Instruction::N(num) => dy -= num as i32,
+                        Instruction::S(num) => dy += num as i32,
+                        Instruction::E(num) => dx += num as i32,
+                        Instruction::W(num) => dx -= num as i32,
+                        _ => break
+                    }
+                    i += 1;
+                }
+                simplified.extend(shift(dx, dy));
+            },
+            Instruction::L(_) | Instruction::R(_) => {
+                let mut deg = 0;
+                while i < instructions.len() {
+                    match instructions[i] {
+                        Instruction::R(num) => deg += num as i32,
+                        Instruction::L(num) => deg -= num as i32,
+                        _ => break
+                    }
+                    i += 1;
+                }
+                simplified.extend(turn(deg));
+            },
+            Instruction::F(_) => {
+                let mut distance = 0;
+                while let Some(Instruction::F(num)) = instructions.get(i) {
+                    distance += num;
+                    i += 1;
+                }
+                if distance > 0 {
+                    simplified.push(Instruction::F(distance));
+                }
+            }
+        }
+    }
+    simplified
+}
+
+fn end_state<S: Ship>(mut ship: S, instructions: &[Instruction]) -> Result<ShipState, NavigationError> {
+    navigate(&mut ship, instructions)?;
+    Ok(ship.state())
+}
+
+#[derive(Debug, Clone)]
 struct WrongShip {
     x: i32,
     y: i32,
     fx: i32,
-    fy: i32
+    fy: i32,
+    compass: Compass
+}
+
+impl WrongShip {
+    fn with_compass(compass: Compass) -> WrongShip {
+        WrongShip { x: 0, y: 0, fx: 1, fy: 0, compass }
+    }
 }
 
 impl Ship for WrongShip {
+    const VECTOR: &'static str = "heading";
     fn create() -> WrongShip {
-        WrongShip { x: 0, y: 0, fx: 1, fy: 0 }
+        WrongShip::with_compass(Compass::Four)
     }
-    fn perform(&mut self, instruction: &Instruction) {
+    fn perform(&mut self, instruction: &Instruction) -> Result<(), NavigationError> {
         match instruction {
             Instruction::N(num) => self.y -= *num as i32,
             Instruction::S(num) => self.y += *num as i32,
             Instruction::E(num) => self.x += *num as i32,
             Instruction::W(num) => self.x -= *num as i32,
             Instruction::R(num) => {
-                let (nfx, nfy) = rotate(self.fx, self.fy, *num as i32);
+                let (nfx, nfy) = rotate_heading(self.fx, self.fy, *num as i32, self.compass)?;
                 self.fx = nfx;
                 self.fy = nfy;
             },
             Instruction::L(num) => {
-                let (nfx, nfy) = rotate(self.fx, self.fy, -(*num as i32));
+                let (nfx, nfy) = rotate_heading(self.fx, self.fy, -(*num as i32), self.compass)?;
                 self.fx = nfx;
                 self.fy = nfy;
             },
@@ -62,13 +213,26 @@ impl Ship for WrongShip {
                 self.y += self.fy * *num as i32;
             }
         }
+        Ok(())
     }
     fn distance_from(&self, x: i32, y: i32) -> i32 {
         (x - self.x).abs() + (y - self.y).abs()
     }
+    fn state(&self) -> ShipState {
+        ShipState { x: self.x, y: self.y, vx: self.fx, vy: self.fy }
+    }
+    fn homing(&self) -> Vec<Instruction> {
+        let mut instructions = shift(-self.x, -self.y);
+        let step = if self.compass == Compass::Eight { 45 } else { 90 };
+        let deg = (0..360).step_by(step)
+            .find(|deg| matches!(rotate_heading(self.fx, self.fy, *deg, self.compass), Ok((1, 0))))
+            .expect("Heading must rotate back to east");
+        instructions.extend(turn(deg));
+        instructions
+    }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 struct RightShip {
     x: i32,
     y: i32,
@@ -77,22 +241,23 @@ struct RightShip {
 }
 
 impl Ship for RightShip {
+    const VECTOR: &'static str = "waypoint";
     fn create() -> RightShip {
         RightShip { x: 0, y: 0, wx: 10, wy: -1 }
     }
-    fn perform(&mut self, instruction: &Instruction) {
+    fn perform(&mut self, instruction: &Instruction) -> Result<(), NavigationError> {
         match instruction {
             Instruction::N(num) => self.wy -= *num as i32,
             Instruction::S(num) => self.wy += *num as i32,
             Instruction::E(num) => self.wx += *num as i32,
             Instruction::W(num) => self.wx -= *num as i32,
             Instruction::R(num) => {
-                let (nwx, nwy) = rotate(self.wx, self.wy, *num as i32);
+                let (nwx, nwy) = rotate(self.wx, self.wy, *num as i32)?;
                 self.wx = nwx;
                 self.wy = nwy;
             },
             Instruction::L(num) => {
-                let (nwx, nwy) = rotate(self.wx, self.wy, -(*num as i32));
+                let (nwx, nwy) = rotate(self.wx, self.wy, -(*num as i32))?;
                 self.wx = nwx;
                 self.wy = nwy;
             },
@@ -101,10 +266,26 @@ impl Ship for RightShip {
                 self.y += self.wy * *num as i32;
             }
         }
+        Ok(())
     }
     fn distance_from(&self, x: i32, y: i32) -> i32 {
         (x - self.x).abs() + (y - self.y).abs()
     }
+    fn state(&self) -> ShipState {
+        ShipState { x: self.x, y: self.y, vx: self.wx, vy: self.wy }
+    }
+    fn homing(&self) -> Vec<Instruction> {
+        let home = RightShip::create();
+        if self.x == 0 && self.y == 0 {
+            return shift(home.wx - self.wx, home.wy - self.wy);
+        }
+        let g = gcd(self.x.abs(), self.y.abs());
+        let (tx, ty) = (-self.x / g, -self.y / g);
+        let mut instructions = shift(tx - self.wx, ty - self.wy);
+        instructions.push(Instruction::F(g as u32));
+        instructions.extend(shift(home.wx - tx, home.wy - ty));
+        instructions
+    }
 }
 
 pub struct Day12 {}
@@ -114,41 +295,178 @@ impl Day for Day12 {
     type Output = i32;
 
     fn read() -> Vec<Instruction> {
-        let mut data: Vec<Instruction> = vec![];
         let file = File::open("./src/day12/input").expect("Input file must exist");
-        for line in BufReader::new(file).lines() {
-            let line = line.expect("Line must be present");
-            let mut chars = line.chars();
-            let c = chars.next().expect("First char should be present");
-            let num = chars.as_str().parse::<u32>().expect("Should contain integer");
-            let instruction = match c {
-                'N' => Instruction::N(num),
-                'E' => Instruction::E(num),
-                'W' => Instruction::W(num),
-                'S' => Instruction::S(num),
-                'L' => Instruction::L(num),
-                'R' => Instruction::R(num),
-                'F' => Instruction::F(num),
-                _ => panic!("Unknown instruction: {:?}", c)
-            };
-            data.push(instruction);
-        }
-        data
+        parse_instructions(BufReader::new(file))
     }
 
     fn part1(input: &Vec<Instruction>) -> i32 {
         let mut ship = WrongShip::create();
-        for instruction in input {
-            ship.perform(instruction);
-        }
+        navigate(&mut ship, input).unwrap_or_else(|e| panic!("Navigation failed: {}", e));
         ship.distance_from(0, 0)
     }
 
     fn part2(input: &Vec<Instruction>) -> i32 {
         let mut ship = RightShip::create();
-        for instruction in input {
-            ship.perform(instruction);
-        }
+        navigate(&mut ship, input).unwrap_or_else(|e| panic!("Navigation failed: {}", e));
         ship.distance_from(0, 0)
     }
+
+    fn command(name: &str, args: &[String]) {
+        let instructions = match flag_value(args, "--path") {
+            Some(path) => parse_instructions(BufReader::new(File::open(path).expect("Instruction file must exist"))),
+            None => Self::read()
+        };
+        let compass = match flag_value(args, "--compass").as_deref() {
+            None | Some("4") => Compass::Four,
+            Some("8") => Compass::Eight,
+            Some(other) => panic!("Unknown compass: {:?}", other)
+        };
+        match name {
+            "navigate" | "voyage" | "inverse" | "simplify" => {
+                match flag_value(args, "--ship").as_deref() {
+                    None | Some("heading") => ship_command(name, WrongShip::with_compass(compass), &instructions, args),
+                    Some("waypoint") => ship_command(name, RightShip::create(), &instructions, args),
+                    Some(other) => panic!("Unknown ship: {:?}", other)
+                }
+            },
+            _ => println!("Unknown command: {:?}", name)
+        }
+    }
+}
+
+fn ship_command<S: Ship + Clone>(name: &str, mut ship: S, instructions: &[Instruction], args: &[String]) {
+    if name == "inverse" {
+        match inverse(ship.clone(), instructions) {
+            Ok(inverse) => {
+                for instruction in &inverse {
+                    println!("{}", instruction);
+                }
+                let round_trip: Vec<Instruction> = instructions.iter().chain(inverse.iter()).cloned().collect();
+                let returned = end_state(ship.clone(), &round_trip).map(|state| state == ship.state()).unwrap_or(false);
+                println!("{} instructions, returns to origin: {}", inverse.len(), returned);
+            },
+            Err(e) => println!("Navigation failed: {}", e)
+        }
+        return;
+    }
+    if name == "simplify" {
+        let simplified = simplify(instructions);
+        for instruction in &simplified {
+            println!("{}", instruction);
+        }
+        let matches = end_state(ship.clone(), instructions).ok() == end_state(ship, &simplified).ok();
+        println!("{} -> {} instructions, end states match: {}", instructions.len(), simplified.len(), matches);
+        return;
+    }
+    if name == "voyage" {
+        let voyage = record_voyage(&mut ship, instructions);
+        let csv = voyage.to_csv(S::VECTOR);
+        match flag_value(args, "--csv") {
+            Some(path) => fs::write(path, csv).expect("Csv file must be writable"),
+            None => print!("{}", csv)
+        }
+        if let Some(path) = flag_value(args, "--svg") {
+            fs::write(path, voyage.to_svg()).expect("Svg file must be writable");
+        }
+        if let Some((step, e)) = &voyage.error {
+            println!("Navigation failed at step {}: {}", step, e);
+        }
+        return;
+    }
+    match navigate(&mut ship, instructions) {
+        Ok(()) => println!("{}", ship.distance_from(0, 0)),
+        Err(e) => println!("Navigation failed: {}", e)
+    }
+}
+
+pub struct Voyage<'a> {
+    entries: Vec<(Option<&'a Instruction>, ShipState)>,
+    error: Option<(usize, NavigationError)>
+}
+
+fn record_voyage<'a, S: Ship>(ship: &mut S, instructions: &'a [Instruction]) -> Voyage<'a> {
+    let mut voyage = Voyage { entries: vec![(None, ship.state())], error: None };
+    for (step, instruction) in instructions.iter().enumerate() {
+        if let Err(e) = ship.perform(instruction) {
+            voyage.error = Some((step + 1, e));
+            break;
+        }
+        voyage.entries.push((Some(instruction), ship.state()));
+    }
+    voyage
+}
+
+impl<'a> Voyage<'a> {
+    fn to_csv(&self, vector: &str) -> String {
+        let mut csv = format!("step,instruction,x,y,{0}_x,{0}_y\n", vector);
+        for (step, (instruction, state)) in self.entries.iter().enumerate() {
+            let instruction = instruction.map(|i| i.to_string()).unwrap_or_default();
+            csv.push_str(&format!("{},{},{},{},{},{}\n", step, instruction, state.x, state.y, state.vx, state.vy));
+        }
+        csv
+    }
+
+    fn to_svg(&self) -> String {
+        let points = self.entries.iter().map(|(_, s)| (s.x, s.y))
+            .chain(self.entries.iter().map(|(_, s)| (s.x + s.vx, s.y + s.vy)));
+        let (mut min_x, mut min_y, mut max_x, mut max_y) = (0, 0, 0, 0);
+        for (x, y) in points {
+            min_x = min_x.min(x);
+            min_y = min_y.min(y);
+            max_x = max_x.max(x);
+            max_y = max_y.max(y);
+        }
+        let margin = ((max_x - min_x).max(max_y - min_y) / 20).max(1);
+        let stroke = (margin as f64 / 10.0).max(0.5);
+        let mut svg = format!(
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" viewBox=\"{} {} {} {}\">\n",
+            min_x - margin, min_y - margin, max_x - min_x + 2 * margin, max_y - min_y + 2 * margin
+        );
+        for (_, state) in &self.entries {
+            svg.push_str(&format!(
+                "  <line x1=\"{}\" y1=\"{}\" x2=\"{}\" y2=\"{}\" stroke=\"orange\" stroke-width=\"{}\" stroke-opacity=\"0.4\"/>\n",
+                state.x, state.y, state.x + state.vx, state.y + state.vy, stroke / 2.0
+            ));
+        }
+        let route: Vec<String> = self.entries.iter().map(|(_, s)| format!("{},{}", s.x, s.y)).collect();
+        svg.push_str(&format!(
+            "  <polyline points=\"{}\" fill=\"none\" stroke=\"steelblue\" stroke-width=\"{}\"/>\n",
+            route.join(" "), stroke
+        ));
+        if let Some((_, last)) = self.entries.last() {
+            svg.push_str(&format!("  <circle cx=\"0\" cy=\"0\" r=\"{}\" fill=\"green\"/>\n", stroke * 3.0));
+            svg.push_str(&format!("  <circle cx=\"{}\" cy=\"{}\" r=\"{}\" fill=\"red\"/>\n", last.x, last.y, stroke * 3.0));
+        }
+        svg.push_str("</svg>\n");
+        svg
+    }
+}
+
+fn navigate<S: Ship>(ship: &mut S, instructions: &[Instruction]) -> Result<(), NavigationError> {
+    for instruction in instructions {
+        ship.perform(instruction)?;
+    }
+    Ok(())
+}
+
+fn parse_instructions<R: BufRead>(reader: R) -> Vec<Instruction> {
+    let mut data: Vec<Instruction> = vec![];
+    for line in reader.lines() {
+        let line = line.expect("Line must be present");
+        let mut chars = line.chars();
+        let c = chars.next().expect("First char should be present");
+        let num = chars.as_str().parse::<u32>().expect("Should contain integer");
+        let instruction = match c {
+            'N' => Instruction::N(num),
+            'E' => Instruction::E(num),
+            'W' => Instruction::W(num),
+            'S' => Instruction::S(num),
+            'L' => Instruction::L(num),
+            'R' => Instruction::R(num),
+            'F' => Instruction::F(num),
+            _ => panic!("Unknown instruction: {:?}", c)
+        };
+        data.push(instruction);
+    }
+    data
 }
\ No newline at end of file
@@ -1,6 +1,84 @@
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::convert::TryFrom;
 use std::fs::File;
 use std::io::{BufRead, BufReader};
-use super::Day;
+use num_bigint::BigInt;
+use super::{crt, Day, flag_value};
+
+pub struct Timetable {
+    buses: Vec<u64>
+}
+
+impl Timetable {
+    fn new(schedule: &[Option<u64>]) -> Timetable {
+        let mut buses: Vec<u64> = schedule.iter().filter_map(|bus| *bus).filter(|bus| *bus > 0).collect();
+        buses.sort_unstable();
+        buses.dedup();
+        Timetable { buses }
+    }
+
+    fn departures_from(&self, time: u64) -> Departures {
+        let upcoming = self.buses.iter().map(|bus| Reverse((next_departure(*bus, time), *bus))).collect();
+        Departures { upcoming }
+    }
+
+    fn departures_between(&self, start: u64, end: u64) -> Vec<(u64, u64)> {
+        self.departures_from(start).take_while(|(departure, _bus)| *departure <= end).collect()
+    }
+
+    fn next_departures(&self, time: u64, count: usize) -> Vec<(u64, u64)> {
+        self.departures_from(time + 1).take(count).collect()
+    }
+
+    fn earliest_apart(&self, first: u64, second: u64, minutes: i64) -> Option<u64> {
+        if !self.buses.contains(&first) || !self.buses.contains(&second) {
+            return None;
+        }
+        let congruences = vec![
+            (BigInt::from(0), BigInt::from(first)),
+            (-BigInt::from(minutes), BigInt::from(second))
+        ];
+        let (mut time, period) = crt::solve(&congruences)?;
+        while &time + minutes < BigInt::from(0) {
+            time += &period;
+        }
+        u64::try_from(&time).ok()
+    }
+
+    fn longest_wait_between(&self, start: u64, end: u64) -> Option<(u64, u64)> {
+        let departures = self.departures_between(start, end);
+        departures.iter().zip(departures.iter().skip(1))
+            .map(|((from, _), (to, _))| (*from, to - from))
+            .max_by_key(|(_from, wait)| *wait)
+    }
+
+    fn longest_wait(&self) -> Option<u64> {
+        self.buses.first().cloned()
+    }
+}
+
+pub struct Departures {
+    upcoming: BinaryHeap<Reverse<(u64, u64)>>
+}
+
+impl Iterator for Departures {
+    type Item = (u64, u64);
+
+    fn next(&mut self) -> Option<(u64, u64)> {
+        let Reverse((departure, bus)) = self.upcoming.pop()?;
+        self.upcoming.push(Reverse((departure + bus, bus)));
+        Some((departure, bus))
+    }
+}
+
+fn next_departure(bus: u64, time: u64) -> u64 {
+    time.div_ceil(bus) * bus
+}
+
+fn number_from(args: &[String], flag: &str) -> u64 {
+    flag_value(args, flag).unwrap_or_else(|| panic!("{} must be present", flag)).parse::<u64>().expect("Value must be integer")
+}
 
 pub struct Day13 {}
 
@@ -30,55 +108,71 @@ impl Day for Day13 {
     }
 
     fn part1(input: &(u64, Vec<Option<u64>>)) -> u64 {
-        let mut departure = input.0;
-        let mut searching = true;
-        let mut selected_bus = 0;
-        while searching {
-            for wrapped in &input.1 {
-                if let Some(bus) = wrapped {
-                    if departure % bus == 0 {
-                        selected_bus = *bus;
-                        searching = false;
-                    }
-                }
-            }
-            if searching {
-                departure += 1;
-            }
-        }
-        (departure - input.0) * selected_bus
+        let timetable = Timetable::new(&input.1);
+        let (departure, bus) = timetable.departures_from(input.0).next().expect("Timetable must have buses");
+        (departure - input.0) * bus
     }
 
     fn part2(input: &(u64, Vec<Option<u64>>)) -> u64 {
-        let mut jump = input.1[0].expect("Value");
-        let mut departure: u64 = 0;
-        let mut searching = true;
-        let mut longest_match = 0;
-        while searching {
-            let mut all_match = true;
-            for (index, wrapped) in input.1.iter().enumerate() {
-                if let Some(bus) = wrapped {
-                    let modded = (departure + index as u64) % bus;
-                    match modded {
-                        0 => {
-                            if index > longest_match {
-                                jump = input.1[0..index].iter().filter_map(|o| o.as_ref()).fold(1, |p, i| p * i);
-                                longest_match = index;
-                            }
-                        },
-                        _ => {
-                            all_match = false;
-                            break;
-                        }
+        let departure = earliest_departure(&input.1).expect("Schedule must be satisfiable");
+        u64::try_from(&departure).expect("Departure must fit in u64")
+    }
+
+    fn command(name: &str, args: &[String]) {
+        let buses = match flag_value(args, "--schedule") {
+            Some(schedule) => parse_schedule(&schedule),
+            None => Self::read().1
+        };
+        let timetable = Timetable::new(&buses);
+        match name {
+            "departures" => {
+                for (departure, bus) in timetable.departures_between(number_from(args, "--from"), number_from(args, "--to")) {
+                    println!("{}: bus {}", departure, bus);
+                }
+            },
+            "next" => {
+                let count = flag_value(args, "--count").map(|c| c.parse::<usize>().expect("Count must be integer")).unwrap_or(10);
+                for (departure, bus) in timetable.next_departures(number_from(args, "--after"), count) {
+                    println!("{}: bus {}", departure, bus);
+                }
+            },
+            "apart" => {
+                let minutes = flag_value(args, "--minutes").map(|m| m.parse::<i64>().expect("Minutes must be integer")).unwrap_or(0);
+                let (first, second) = (number_from(args, "--a"), number_from(args, "--b"));
+                match timetable.earliest_apart(first, second, minutes) {
+                    Some(time) => println!("bus {} departs at {}, bus {} at {}", first, time, second, time as i64 + minutes),
+                    None if !timetable.buses.contains(&first) || !timetable.buses.contains(&second) => println!("Buses {} and {} are not both in the timetable", first, second),
+                    None => println!("Buses {} and {} never depart {} minutes apart", first, second, minutes)
+                }
+            },
+            "longest-wait" => {
+                match (flag_value(args, "--from"), flag_value(args, "--to")) {
+                    (Some(_), Some(_)) => match timetable.longest_wait_between(number_from(args, "--from"), number_from(args, "--to")) {
+                        Some((from, wait)) => println!("{} minutes after the departure at {}", wait, from),
+                        None => println!("Fewer than two departures in window")
+                    },
+                    _ => match timetable.longest_wait() {
+                        Some(wait) => println!("{} minutes", wait),
+                        None => println!("Timetable has no buses")
                     }
                 }
-            }
-            if all_match {
-                searching = false;
-            } else {
-                departure += jump;
-            }
+            },
+            "solve" => match earliest_departure(&buses) {
+                Some(departure) => println!("{}", departure),
+                None => println!("No departure time satisfies the schedule")
+            },
+            _ => println!("Unknown command: {:?}", name)
         }
-        departure
     }
+}
+
+fn parse_schedule(schedule: &str) -> Vec<Option<u64>> {
+    schedule.trim().split(',').map(|b| b.parse::<u64>().ok()).collect()
+}
+
+fn earliest_departure(buses: &[Option<u64>]) -> Option<BigInt> {
+    let congruences: Vec<(BigInt, BigInt)> = buses.iter().enumerate()
+        .filter_map(|(index, bus)| bus.map(|bus| (-BigInt::from(index), BigInt::from(bus))))
+        .collect();
+    crt::solve(&congruences).map(|(departure, _period)| departure)
 }
\ No newline at end of file
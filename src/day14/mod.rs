@@ -1,132 +1,270 @@
-use std::fs::File;
-use std::io::{BufRead, BufReader};
-use std::collections::HashMap;
-use std::collections::hash_map::Entry;
-use super::Day;
+use std::fmt;
+use std::fs;
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use super::{Day, flag_value};
 
-#[derive(Debug)]
-pub enum Instruction {
-    UpdateMask(Vec<Option<bool>>),
-    AssignMemory(u64, u64),
+const WORD_WIDTH: u32 = 36;
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Mask {
+    ones: u64,
+    zeros: u64,
+    floating: u64
 }
 
-impl Instruction {
-    fn from(str: &str) -> Instruction {
-        let parts: Vec<&str> = str.split(" = ").collect();
-        if parts[0] == "mask" {
-            let mask: Vec<Option<bool>> = parts[1].chars().map(|c| {
-                match c {
-                    'X' => None,
-                    '0' => Some(false),
-                    '1' => Some(true),
-                    _ => panic!("Invalid value in bitmask: {:?}", c)
-                }
-            }).collect();
-            return Instruction::UpdateMask(mask);
-        } else {
-            let subparts: Vec<&str> = parts[0].split("[").collect();
-            if subparts[0] == "mem" {
-                let location = subparts[1].trim_end_matches(']').parse::<u64>().expect("Should be valid integer");
-                let value = parts[1].parse::<u64>().expect("Should be valid integer");
-                return Instruction::AssignMemory(location, value);
-            } else {
-                panic!("Invalid instruction: {:?}", str);
+impl Mask {
+    fn from(str: &str) -> Result<Mask, String> {
+        let mut mask = Mask::default();
+        for c in str.chars() {
+            mask.ones <<= 1;
+            mask.zeros <<= 1;
+            mask.floating <<= 1;
+            match c {
+                'X' => mask.floating |= 1,
+                '0' => mask.zeros |= 1,
+                '1' => mask.ones |= 1,
+                _ => return Err(format!("Invalid value in bitmask: {:?}", c))
             }
         }
+        Ok(mask)
+    }
+
+    fn initial(width: u32, decoder: Decoder) -> Mask {
+        match decoder {
+            Decoder::Value => Mask { ones: 0, zeros: 0, floating: word_mask(width) },
+            Decoder::Address => Mask { ones: 0, zeros: word_mask(width), floating: 0 }
+        }
+    }
+
+    fn apply(&self, value: u64) -> u64 {
+        (value & self.floating) | self.ones
     }
+
+    fn pattern(&self, location: u64) -> Pattern {
+        Pattern { fixed: (location & self.zeros) | self.ones, floating: self.floating }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Pattern {
+    fixed: u64,
+    floating: u64
 }
 
-fn apply_bitmask(value: &u64, mask: &Vec<Option<bool>>) -> u64 {
-    let mut updated = 0;
-    for (i, mask) in mask.iter().rev().enumerate() {
-        let mut bit: bool = (value >> i) & 0b1 == 0b1;
-        if let Some(mask) = mask {
-            bit = *mask;
+impl Pattern {
+    fn size(&self) -> u128 {
+        1 << self.floating.count_ones()
+    }
+
+    fn overlaps(&self, other: &Pattern) -> bool {
+        (self.fixed ^ other.fixed) & !self.floating & !other.floating == 0
+    }
+
+    fn subtract(&self, other: &Pattern) -> Vec<Pattern> {
+        if !self.overlaps(other) {
+            return vec![*self];
+        }
+        let mut pieces = vec![];
+        let mut remainder = *self;
+        let mut splitting = self.floating & !other.floating;
+        while splitting != 0 {
+            let bit = splitting & splitting.wrapping_neg();
+            splitting &= !bit;
+            remainder.floating &= !bit;
+            pieces.push(Pattern { fixed: remainder.fixed | (!other.fixed & bit), floating: remainder.floating });
+            remainder.fixed |= other.fixed & bit;
         }
-        if bit {
-            updated |= ((bit as u64) << i) as u64;
+        pieces
+    }
+
+    fn addresses(&self) -> FloatingAddresses {
+        FloatingAddresses {
+            base: self.fixed,
+            floating: self.floating,
+            subset: Some(self.floating)
         }
     }
-    updated
 }
 
-fn values_from_masking_bit(bit: &Option<bool>) -> Vec<bool> {
-    if let Some(value) = bit {
-        vec![*value]
-    } else {
-        vec![false, true]
+pub struct FloatingAddresses {
+    base: u64,
+    floating: u64,
+    subset: Option<u64>
+}
+
+impl Iterator for FloatingAddresses {
+    type Item = u64;
+
+    fn next(&mut self) -> Option<u64> {
+        let subset = self.subset?;
+        self.subset = if subset == 0 { None } else { Some((subset - 1) & self.floating) };
+        Some(self.base | subset)
     }
 }
 
-fn generate_all_locations_from_masked_location(location: &Vec<Option<bool>>) -> Vec<Vec<bool>> {
-    if location.is_empty() {
-        vec![]
-    } else if location.len() == 1 {
-        values_from_masking_bit(&location[0]).iter().map(|v| vec![*v]).collect()
-    } else {
-        values_from_masking_bit(&location[0]).iter().flat_map(|v| {
-            let mut locations = vec![];
-            let remainder = location[1..location.len()].to_vec().clone();
-            let suffixes = generate_all_locations_from_masked_location(&remainder);
-            for suffix in suffixes {
-                let mut value = vec![*v];
-                for bit in suffix {
-                    value.push(bit);
-                }
-                locations.push(value);
-            }
-            locations
-        }).collect()
+#[derive(Debug, Default)]
+pub struct SymbolicMemory {
+    entries: Vec<(Pattern, u64)>
+}
+
+impl SymbolicMemory {
+    fn write(&mut self, pattern: Pattern, value: u64) {
+        self.entries = self.entries.iter()
+            .flat_map(|(existing, stored)| existing.subtract(&pattern).into_iter().map(move |piece| (piece, *stored)))
+            .collect();
+        if value != 0 {
+            self.entries.push((pattern, value));
+        }
     }
+
+    fn sum(&self) -> u128 {
+        self.entries.iter().map(|(pattern, value)| pattern.size() * *value as u128).sum()
+    }
+}
+
+#[derive(Debug)]
+pub enum Instruction {
+    UpdateMask(Mask),
+    AssignMemory(u64, u64),
 }
 
-fn generate_locations_from_bitmask(value: &u64, mask: &Vec<Option<bool>>) -> Vec<u64> {
-    let masked: Vec<Option<bool>> = mask.iter().rev().enumerate().map(|(i,mask)| {
-        let bit: bool = (value >> i) & 0b1 == 0b1;
-        if let Some(mask) = mask {
-            if *mask {
-                Some(true)
-            } else {
-                Some(bit)
+impl Instruction {
+    fn parse(str: &str, width: u32) -> Result<Instruction, String> {
+        let (target, value) = str.split_once(" = ").ok_or_else(|| format!("Expected assignment: {:?}", str))?;
+        if target == "mask" {
+            if value.len() != width as usize {
+                return Err(format!("Mask has {} bits, expected {}", value.len(), width));
             }
+            Ok(Instruction::UpdateMask(Mask::from(value)?))
+        } else if let Some(location) = target.strip_prefix("mem[").and_then(|rest| rest.strip_suffix(']')) {
+            let location = location.parse::<u64>().map_err(|_| format!("Invalid address: {:?}", location))?;
+            let value = value.parse::<u64>().map_err(|_| format!("Invalid value: {:?}", value))?;
+            Ok(Instruction::AssignMemory(location, value))
         } else {
-            None
+            Err(format!("Invalid instruction: {:?}", str))
         }
-    }).rev().collect();
-    let locations = generate_all_locations_from_masked_location(&masked);
-    locations.iter().map(|l| convert_location_bits_to_location(l)).collect()
+    }
 }
 
-fn convert_location_bits_to_location(bits: &Vec<bool>) -> u64 {
-    let mut updated = 0;
-    for (i, bit) in bits.iter().rev().enumerate() {
-        if *bit {
-            updated |= ((*bit as u64) << i) as u64;
+#[derive(Debug)]
+pub struct ParseError {
+    line: usize,
+    message: String
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+fn parse_program(source: &str, width: u32) -> Result<Vec<Instruction>, ParseError> {
+    source.lines().enumerate()
+        .map(|(index, line)| (index, line.trim()))
+        .filter(|(_index, line)| !line.is_empty())
+        .map(|(index, line)| Instruction::parse(line, width).map_err(|message| ParseError { line: index + 1, message }))
+        .collect()
+}
+
+fn word_mask(width: u32) -> u64 {
+    if width >= 64 { u64::MAX } else { (1 << width) - 1 }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Decoder {
+    Value,
+    Address
+}
+
+#[derive(Debug)]
+pub struct Write {
+    address: u64,
+    value: u64,
+    previous: u64
+}
+
+impl fmt::Display for Write {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "mem[{}] = {} (was {})", self.address, self.value, self.previous)
+    }
+}
+
+pub struct Emulator {
+    width: u32,
+    decoder: Decoder,
+    mask: Mask,
+    memory: BTreeMap<u64, u64>,
+    trace: Vec<Write>
+}
+
+impl Emulator {
+    fn new(width: u32, decoder: Decoder) -> Emulator {
+        assert!(width > 0 && width <= 64, "Word width must be between 1 and 64 bits");
+        Emulator { width, decoder, mask: Mask::initial(width, decoder), memory: BTreeMap::new(), trace: vec![] }
+    }
+
+    fn store(&mut self, address: u64, value: u64) {
+        let previous = self.memory.insert(address, value).unwrap_or(0);
+        self.trace.push(Write { address, value, previous });
+    }
+
+    fn execute(&mut self, instruction: &Instruction) {
+        match instruction {
+            Instruction::UpdateMask(mask) => self.mask = *mask,
+            Instruction::AssignMemory(location, value) => {
+                let location = location & word_mask(self.width);
+                match self.decoder {
+                    Decoder::Value => self.store(location, self.mask.apply(*value)),
+                    Decoder::Address => {
+                        for address in self.mask.pattern(location).addresses() {
+                            self.store(address, *value);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn run(&mut self, program: &[Instruction]) {
+        for instruction in program {
+            self.execute(instruction);
         }
     }
-    updated
+
+    fn sum(&self) -> u128 {
+        self.memory.values().map(|value| *value as u128).sum()
+    }
 }
 
-fn _format_bitmask(mask: &Vec<Option<bool>>) -> String {
-    mask.iter().map(|o| {
-        if let Some(true) = o {
-            '1'
-        } else if let Some(false) = o {
-            '0'
-        } else {
-            'X'
+fn symbolic_sum(input: &[Instruction], width: u32) -> u128 {
+    let mut memory = SymbolicMemory::default();
+    let mut current_mask = Mask::initial(width, Decoder::Address);
+    for instruction in input {
+        match instruction {
+            Instruction::AssignMemory(location, value) => memory.write(current_mask.pattern(location & word_mask(width)), *value),
+            Instruction::UpdateMask(mask) => current_mask = *mask
         }
-    }).collect()
+    }
+    memory.sum()
 }
 
-fn _format_bits(mask: &Vec<bool>) -> String {
-    mask.iter().map(|o| {
-        if *o {
-            '1'
-        } else {
-            '0'
+fn emulator_from(args: &[String]) -> (Emulator, Vec<Instruction>) {
+    let width = flag_value(args, "--width").map(|w| w.parse::<u32>().expect("Width must be integer")).unwrap_or(WORD_WIDTH);
+    let decoder = match flag_value(args, "--decoder").as_deref() {
+        Some("value") | None => Decoder::Value,
+        Some("address") => Decoder::Address,
+        Some(other) => panic!("Unknown decoder: {:?}", other)
+    };
+    let path = flag_value(args, "--path").unwrap_or_else(|| String::from("./src/day14/input"));
+    let source = fs::read_to_string(&path).expect("Input file must exist");
+    match parse_program(&source, width) {
+        Ok(program) => (Emulator::new(width, decoder), program),
+        Err(error) => {
+            println!("Invalid program: {}", error);
+            std::process::exit(1);
         }
-    }).collect()
+    }
 }
 
 pub struct Day14 {}
@@ -136,75 +274,45 @@ impl Day for Day14 {
     type Output = u64;
 
     fn read() -> Vec<Instruction> {
-        let mut data = vec![];
-        let file = File::open("./src/day14/input").expect("Input file must exist");
-        for line in BufReader::new(file).lines() {
-            let line = line.expect("Should be line");
-            let line = line.trim();
-            data.push(Instruction::from(line));
-        }
-        data
+        let source = fs::read_to_string("./src/day14/input").expect("Input file must exist");
+        parse_program(&source, WORD_WIDTH).unwrap_or_else(|e| panic!("Invalid program: {}", e))
     }
 
     fn part1(input: &Vec<Instruction>) -> u64 {
-        let mut memory: HashMap<u64,u64> = HashMap::new();
-        let mut current_mask: Vec<Option<bool>> = [None; 36].iter().map(|o| *o).collect();
-        for instruction in input {
-            match instruction {
-                Instruction::AssignMemory(location, value) => {
-                    let value = apply_bitmask(&value, &current_mask);
-                    match memory.entry(*location) {
-                        Entry::Occupied(mut entry) => {
-                            if value == 0 {
-                                entry.remove_entry();
-                            } else {
-                                entry.insert(value);
-                            }
-                        },
-                        Entry::Vacant(entry) => {
-                            if value != 0 {
-                                entry.insert(value);
-                            }
-                        }
-                    }
-                },
-                Instruction::UpdateMask(mask) => {
-                    current_mask = mask.clone();
-                }
-            }
-        }
-        memory.values().fold(0, |acc, i| acc + i)
+        let mut emulator = Emulator::new(WORD_WIDTH, Decoder::Value);
+        emulator.run(input);
+        u64::try_from(emulator.sum()).expect("Sum must fit in u64")
     }
 
     fn part2(input: &Vec<Instruction>) -> u64 {
-        let mut memory: HashMap<u64,u64> = HashMap::new();
-        let mut current_mask: Vec<Option<bool>> = [None; 36].iter().map(|o| *o).collect();
-        for instruction in input {
-            match instruction {
-                Instruction::AssignMemory(location, value) => {
-                    let locations = generate_locations_from_bitmask(&location, &current_mask);
-                    for location in locations {
-                        match memory.entry(location) {
-                            Entry::Occupied(mut entry) => {
-                                if *value == 0 {
-                                    entry.remove_entry();
-                                } else {
-                                    entry.insert(*value);
-                                }
-                            },
-                            Entry::Vacant(entry) => {
-                                if *value != 0 {
-                                    entry.insert(*value);
-                                }
-                            }
-                        }
-                    }
-                },
-                Instruction::UpdateMask(mask) => {
-                    current_mask = mask.clone();
+        let sum = symbolic_sum(input, WORD_WIDTH);
+        u64::try_from(sum).expect("Sum must fit in u64")
+    }
+
+    fn command(name: &str, args: &[String]) {
+        let (mut emulator, program) = emulator_from(args);
+        match name {
+            "trace" => {
+                emulator.run(&program);
+                for write in &emulator.trace {
+                    println!("{}", write);
                 }
-            }
+            },
+            "dump" => {
+                emulator.run(&program);
+                for (address, value) in emulator.memory.iter().filter(|(_address, value)| **value != 0) {
+                    println!("{:0width$b} ({}): {}", address, address, value, width = emulator.width as usize);
+                }
+                println!("sum: {}", emulator.sum());
+            },
+            "verify" => {
+                let symbolic = symbolic_sum(&program, emulator.width);
+                let mut emulator = Emulator::new(emulator.width, Decoder::Address);
+                emulator.run(&program);
+                println!("symbolic: {}, concrete: {}", symbolic, emulator.sum());
+                println!("{}", if symbolic == emulator.sum() { "Sums match" } else { "Sums differ" });
+            },
+            _ => println!("Unknown command: {:?}", name)
         }
-        memory.values().fold(0, |acc, i| acc + i)
     }
 }
\ No newline at end of file
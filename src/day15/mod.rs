@@ -1,47 +1,181 @@
 use std::fs::File;
-use std::io::{BufRead, BufReader};
-use std::collections::HashMap;
-use std::collections::hash_map::Entry;
-use super::Day;
-
-fn next_num(history: &HashMap<u64, u64>, current_num: u64, current_turn: u64) -> u64 {
-    match history.get(&current_num) {
-        Some(turn) => current_turn - turn,
-        None => 0
-    }
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::collections::{BTreeMap, HashMap};
+use super::{Day, flag_value};
+
+pub struct VanEck {
+    prelude: Vec<u64>,
+    turn: u32,
+    last: Option<u64>,
+    low: Vec<u32>,
+    high: HashMap<u64, u32>
 }
 
-fn play_game(prelude: &Vec<u64>, turns: u64) -> u64 {
-    let mut history: HashMap<u64, u64> = HashMap::new();
-    for (turn, num) in prelude.iter().enumerate() {
-        if turn == prelude.len() - 1 {
-            break;
-        }
-        match history.entry(*num) {
-            Entry::Occupied(mut entry) => { 
-                entry.insert(turn as u64);
-            },
-            Entry::Vacant(entry) => { 
-                entry.insert(turn as u64);
-            }
+impl VanEck {
+    fn new(prelude: &[u64], capacity: usize) -> VanEck {
+        VanEck { prelude: prelude.to_vec(), turn: 0, last: None, low: vec![0; capacity], high: HashMap::new() }
+    }
+
+    fn seen(&self, number: u64) -> Option<u32> {
+        let turn = match self.low.get(number as usize) {
+            Some(turn) => *turn,
+            None => self.high.get(&number).cloned().unwrap_or(0)
         };
+        turn.checked_sub(1)
     }
-    let mut current = prelude[prelude.len() - 1];
-    let start = prelude.len() as u64 - 1;
-    let end = turns - 1;
-    for turn in start..end {
-        let next = next_num(&history, current, turn);
-        match history.entry(current) {
-            Entry::Occupied(mut entry) => { 
-                entry.insert(turn);
-            },
-            Entry::Vacant(entry) => { 
-                entry.insert(turn);
+
+    fn remember(&mut self, number: u64, turn: u32) {
+        match self.low.get_mut(number as usize) {
+            Some(slot) => *slot = turn + 1,
+            None => {
+                self.high.insert(number, turn + 1);
             }
         }
-        current = next;
     }
-    current
+
+    fn reserve(&mut self, capacity: usize) {
+        if capacity <= self.low.len() {
+            return;
+        }
+        self.low.resize(capacity, 0);
+        let migrated: Vec<u64> = self.high.keys().filter(|number| (**number as usize) < capacity).cloned().collect();
+        for number in migrated {
+            let turn = self.high.remove(&number).expect("Number must be present");
+            self.low[number as usize] = turn;
+        }
+    }
+
+    fn advance_to(&mut self, turns: u64) -> Option<u64> {
+        while (self.turn as u64) < turns {
+            if self.turn as usize >= self.low.len() {
+                self.reserve((self.low.len() + GROWTH_CHUNK).min(turns as usize));
+            }
+            self.next()?;
+        }
+        self.last
+    }
+
+    fn save(&self, path: &str) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        writer.write_all(CHECKPOINT_MAGIC)?;
+        writer.write_all(&self.turn.to_le_bytes())?;
+        writer.write_all(&self.last.unwrap_or(0).to_le_bytes())?;
+        write_len(&mut writer, self.prelude.len())?;
+        for number in &self.prelude {
+            writer.write_all(&number.to_le_bytes())?;
+        }
+        let used = self.low.iter().rposition(|turn| *turn != 0).map(|i| i + 1).unwrap_or(0);
+        write_len(&mut writer, used)?;
+        for turn in &self.low[..used] {
+            writer.write_all(&turn.to_le_bytes())?;
+        }
+        write_len(&mut writer, self.high.len())?;
+        for (number, turn) in &self.high {
+            writer.write_all(&number.to_le_bytes())?;
+            writer.write_all(&turn.to_le_bytes())?;
+        }
+        writer.flush()
+    }
+
+    fn load(path: &str) -> io::Result<VanEck> {
+        let mut reader = BufReader::new(File::open(path)?);
+        let mut magic = [0u8; 8];
+        reader.read_exact(&mut magic)?;
+        if &magic != CHECKPOINT_MAGIC {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "Not a Van Eck checkpoint"));
+        }
+        let turn = read_u32(&mut reader)?;
+        let last = read_u64(&mut reader)?;
+        let prelude = (0..read_u64(&mut reader)?).map(|_| read_u64(&mut reader)).collect::<io::Result<Vec<u64>>>()?;
+        let low = (0..read_u64(&mut reader)?).map(|_| read_u32(&mut reader)).collect::<io::Result<Vec<u32>>>()?;
+        let mut high = HashMap::new();
+        for _ in 0..read_u64(&mut reader)? {
+            let number = read_u64(&mut reader)?;
+            high.insert(number, read_u32(&mut reader)?);
+        }
+        let last = if turn == 0 { None } else { Some(last) };
+        Ok(VanEck { prelude, turn, last, low, high })
+    }
+}
+
+const CHECKPOINT_MAGIC: &[u8; 8] = b"VANECK01";
+const GROWTH_CHUNK: usize = 1 << 20;
+
+fn write_len<W: Write>(writer: &mut W, len: usize) -> io::Result<()> {
+    writer.write_all(&(len as u64).to_le_bytes())
+}
+
+fn read_u32<R: Read>(reader: &mut R) -> io::Result<u32> {
+    let mut bytes = [0u8; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn read_u64<R: Read>(reader: &mut R) -> io::Result<u64> {
+    let mut bytes = [0u8; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+impl Iterator for VanEck {
+    type Item = u64;
+
+    fn next(&mut self) -> Option<u64> {
+        let spoken = match (self.prelude.get(self.turn as usize), self.last) {
+            (Some(number), _) => *number,
+            (None, Some(last)) => self.seen(last).map(|seen| (self.turn - 1 - seen) as u64).unwrap_or(0),
+            (None, None) => return None
+        };
+        if let Some(last) = self.last {
+            self.remember(last, self.turn - 1);
+        }
+        self.last = Some(spoken);
+        self.turn = self.turn.checked_add(1).expect("Turn count must fit in u32");
+        Some(spoken)
+    }
+}
+
+fn play_game(prelude: &[u64], turns: u64) -> Option<u64> {
+    let last = turns.checked_sub(1)? as usize;
+    VanEck::new(prelude, turns as usize).nth(last)
+}
+
+fn first_occurrence(prelude: &[u64], number: u64, limit: u64) -> Option<u64> {
+    VanEck::new(prelude, limit as usize).take(limit as usize).position(|spoken| spoken == number).map(|index| index as u64 + 1)
+}
+
+fn count_zeros(prelude: &[u64], turns: u64) -> usize {
+    VanEck::new(prelude, turns as usize).take(turns as usize).filter(|spoken| *spoken == 0).count()
+}
+
+fn largest_gap(prelude: &[u64], turns: u64) -> Option<(u64, u64)> {
+    VanEck::new(prelude, turns as usize).take(turns as usize).enumerate()
+        .skip(prelude.len())
+        .max_by(|(a_index, a), (b_index, b)| a.cmp(b).then(b_index.cmp(a_index)))
+        .map(|(index, gap)| (index as u64 + 1, gap))
+}
+
+fn distribution(prelude: &[u64], turns: u64) -> BTreeMap<u64, u64> {
+    let mut counts = BTreeMap::new();
+    for spoken in VanEck::new(prelude, turns as usize).take(turns as usize) {
+        *counts.entry(spoken).or_insert(0) += 1;
+    }
+    counts
+}
+
+fn turns_from(args: &[String], default: u64) -> u64 {
+    flag_value(args, "--turns").map(|t| t.parse::<u64>().expect("Turns must be integer")).unwrap_or(default)
+}
+
+fn prelude_from(args: &[String]) -> Vec<u64> {
+    match flag_value(args, "--prelude") {
+        Some(prelude) => parse_prelude(&prelude),
+        None => Day15::read()
+    }
+}
+
+fn parse_prelude(line: &str) -> Vec<u64> {
+    line.trim().split(',').map(|n| n.trim().parse::<u64>().expect("Should be integer")).collect()
 }
 
 pub struct Day15 {}
@@ -53,15 +187,84 @@ impl Day for Day15 {
     fn read() -> Vec<u64> {
         let file = File::open("./src/day15/input").expect("Input file must exist");
         let line = BufReader::new(file).lines().next().expect("Should be line").expect("Should be line");
-        let line = line.trim();
-        line.split(',').map(|n| n.parse::<u64>().expect("Should be integer")).collect()
+        parse_prelude(&line)
     }
 
     fn part1(input: &Vec<u64>) -> u64 {
-        play_game(input, 2020)
+        play_game(input, 2020).expect("Prelude must not be empty")
     }
 
     fn part2(input: &Vec<u64>) -> u64 {
-        play_game(input, 30000000)       
+        play_game(input, 30000000).expect("Prelude must not be empty")
+    }
+
+    fn command(name: &str, args: &[String]) {
+        if name == "checkpoint" || name == "resume" {
+            let turns = turns_from(args, 30000000);
+            let mut engine = if name == "resume" {
+                match flag_value(args, "--input") {
+                    Some(path) => VanEck::load(&path).unwrap_or_else(|e| panic!("Could not load checkpoint: {}", e)),
+                    None => {
+                        println!("resume requires --input <checkpoint>");
+                        return;
+                    }
+                }
+            } else {
+                VanEck::new(&prelude_from(args), 0)
+            };
+            if engine.turn as u64 > turns {
+                println!("Checkpoint is already at turn {}, past turn {}", engine.turn, turns);
+                return;
+            }
+            match engine.advance_to(turns) {
+                Some(spoken) => println!("turn {}: {}", engine.turn, spoken),
+                None if turns == 0 => println!("No number is spoken within 0 turns"),
+                None => println!("Prelude is empty")
+            }
+            if let Some(path) = flag_value(args, "--output") {
+                engine.save(&path).unwrap_or_else(|e| panic!("Could not save checkpoint: {}", e));
+            }
+            return;
+        }
+        let prelude = prelude_from(args);
+        match name {
+            "play" => match play_game(&prelude, turns_from(args, 2020)) {
+                Some(spoken) => println!("{}", spoken),
+                None => println!("No number is spoken within that many turns")
+            },
+            "sequence" => {
+                let skip = flag_value(args, "--skip").map(|s| s.parse::<usize>().expect("Skip must be integer")).unwrap_or(0);
+                let take = flag_value(args, "--take").map(|t| t.parse::<usize>().expect("Take must be integer")).unwrap_or(10);
+                let spoken: Vec<u64> = VanEck::new(&prelude, skip + take).skip(skip).take(take).collect();
+                println!("{:?}", spoken);
+            },
+            "first" => {
+                let number = flag_value(args, "--number").expect("--number must be present").parse::<u64>().expect("Number must be integer");
+                let limit = turns_from(args, 30000000);
+                match first_occurrence(&prelude, number, limit) {
+                    Some(turn) => println!("{} is first spoken on turn {}", number, turn),
+                    None => println!("{} is not spoken within {} turns", number, limit)
+                }
+            },
+            "zeros" => {
+                let turns = turns_from(args, 2020);
+                println!("{} zeros in {} turns", count_zeros(&prelude, turns), turns);
+            },
+            "largest-gap" => match largest_gap(&prelude, turns_from(args, 2020)) {
+                Some((turn, gap)) => println!("gap of {} spoken on turn {}", gap, turn),
+                None => println!("No numbers spoken after the prelude")
+            },
+            "distribution" => {
+                let top = flag_value(args, "--top").map(|t| t.parse::<usize>().expect("Top must be integer")).unwrap_or(10);
+                let counts = distribution(&prelude, turns_from(args, 2020));
+                let mut frequent: Vec<(&u64, &u64)> = counts.iter().collect();
+                frequent.sort_by(|(a, a_count), (b, b_count)| b_count.cmp(a_count).then(a.cmp(b)));
+                for (number, count) in frequent.into_iter().take(top) {
+                    println!("{}: {}", number, count);
+                }
+                println!("{} distinct numbers", counts.len());
+            },
+            _ => println!("Unknown command: {:?}", name)
+        }
     }
 }
\ No newline at end of file
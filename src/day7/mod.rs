@@ -1,8 +1,10 @@
+use std::fmt;
 use std::fs::File;
-use std::collections::HashMap;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
 use std::io::{BufRead, BufReader};
 use regex::Regex;
-use super::Day;
+use super::{Day, flag_value};
 
 #[derive(Debug)]
 pub struct BagRule {
@@ -10,31 +12,289 @@ pub struct BagRule {
     contains: Vec<(u32,String)>
 }
 
-#[derive(Debug)]
-pub struct Bag {
-    color: String,
-    contents: Vec<(u32, Bag)>
+const TARGET: &str = "shiny gold";
+
+pub struct BagGraph {
+    colors: Vec<String>,
+    ids: HashMap<String, usize>,
+    contents: Vec<Vec<(u32, usize)>>,
+    holders: Vec<Vec<usize>>,
+    contain_cache: RefCell<HashMap<(usize, usize), bool>>,
+    size_cache: RefCell<HashMap<usize, usize>>
 }
 
-impl Bag {
-    fn contains(&self, target: &String) -> bool {
-        let mut result = false;
-        for (_count, bag) in &self.contents {
-            if &bag.color == target {
-                result = true;
-            } else if bag.contains(target) {
-                result = true;
+impl BagGraph {
+    fn new(rules: &[BagRule]) -> BagGraph {
+        let mut graph = BagGraph {
+            colors: vec![],
+            ids: HashMap::new(),
+            contents: vec![],
+            holders: vec![],
+            contain_cache: RefCell::new(HashMap::new()),
+            size_cache: RefCell::new(HashMap::new())
+        };
+        for rule in rules {
+            let outer = graph.id_for(&rule.color);
+            for (count, color) in &rule.contains {
+                let inner = graph.id_for(color);
+                graph.contents[outer].push((*count, inner));
+                graph.holders[inner].push(outer);
             }
         }
+        graph
+    }
+
+    fn id_for(&mut self, color: &str) -> usize {
+        if let Some(id) = self.ids.get(color) {
+            return *id;
+        }
+        let id = self.colors.len();
+        self.colors.push(color.to_string());
+        self.ids.insert(color.to_string(), id);
+        self.contents.push(vec![]);
+        self.holders.push(vec![]);
+        id
+    }
+
+    fn id(&self, color: &str) -> usize {
+        self.lookup(color).unwrap_or_else(|| panic!("Unknown bag color: {:?}", color))
+    }
+
+    fn lookup(&self, color: &str) -> Option<usize> {
+        self.ids.get(color).cloned()
+    }
+
+    fn can_contain(&self, outer: &str, inner: &str) -> Option<bool> {
+        Some(self.can_contain_id(self.lookup(outer)?, self.lookup(inner)?))
+    }
+
+    fn can_contain_id(&self, outer: usize, inner: usize) -> bool {
+        if let Some(result) = self.contain_cache.borrow().get(&(outer, inner)) {
+            return *result;
+        }
+        let result = self.contents[outer].iter().any(|(_count, bag)| *bag == inner || self.can_contain_id(*bag, inner));
+        self.contain_cache.borrow_mut().insert((outer, inner), result);
         result
     }
-    fn bag_size(&self) -> usize {
-        let mut size = 1;
-        for (count, bag) in &self.contents {
-            size += *count as usize * bag.bag_size();
+
+    fn total_inside(&self, color: &str) -> Option<usize> {
+        self.lookup(color).map(|id| self.total_inside_id(id))
+    }
+
+    fn total_inside_id(&self, color: usize) -> usize {
+        if let Some(size) = self.size_cache.borrow().get(&color) {
+            return *size;
         }
+        let size = self.contents[color].iter().map(|(count, bag)| *count as usize * (1 + self.total_inside_id(*bag))).sum();
+        self.size_cache.borrow_mut().insert(color, size);
         size
     }
+
+    fn holders_of(&self, color: &str) -> Option<Vec<&str>> {
+        self.lookup(color).map(|id| self.names(self.reachable(id, true)))
+    }
+
+    fn reachable(&self, start: usize, upward: bool) -> HashSet<usize> {
+        let mut seen: HashSet<usize> = HashSet::new();
+        let mut pending = vec![start];
+        while let Some(current) = pending.pop() {
+            let next: Vec<usize> = if upward {
+                self.holders[current].clone()
+            } else {
+                self.contents[current].iter().map(|(_count, bag)| *bag).collect()
+            };
+            for bag in next {
+                if seen.insert(bag) {
+                    pending.push(bag);
+                }
+            }
+        }
+        seen
+    }
+
+    fn names(&self, ids: HashSet<usize>) -> Vec<&str> {
+        let mut names: Vec<&str> = ids.into_iter().map(|id| self.colors[id].as_str()).collect();
+        names.sort_unstable();
+        names
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct RuleReport {
+    cycles: Vec<Vec<String>>,
+    undefined: Vec<(String, String)>,
+    duplicates: Vec<String>,
+    unreachable: Vec<String>
+}
+
+impl RuleReport {
+    fn is_valid(&self) -> bool {
+        self.cycles.is_empty() && self.undefined.is_empty() && self.duplicates.is_empty()
+    }
+}
+
+impl fmt::Display for RuleReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for cycle in &self.cycles {
+            writeln!(f, "cycle: {}", cycle.join(" -> "))?;
+        }
+        for (holder, color) in &self.undefined {
+            writeln!(f, "undefined: {:?} referenced by {:?}", color, holder)?;
+        }
+        for color in &self.duplicates {
+            writeln!(f, "duplicate: {:?}", color)?;
+        }
+        for color in &self.unreachable {
+            writeln!(f, "unreachable: {:?}", color)?;
+        }
+        Ok(())
+    }
+}
+
+fn validate(rules: &[BagRule], root: Option<&str>) -> RuleReport {
+    let mut report = RuleReport::default();
+    let mut defined: HashSet<&str> = HashSet::new();
+    for rule in rules {
+        if !defined.insert(&rule.color) && !report.duplicates.contains(&rule.color) {
+            report.duplicates.push(rule.color.clone());
+        }
+    }
+    for rule in rules {
+        for (_count, color) in &rule.contains {
+            if !defined.contains(color.as_str()) {
+                report.undefined.push((rule.color.clone(), color.clone()));
+            }
+        }
+    }
+    let graph = BagGraph::new(rules);
+    let mut states: Vec<Visit> = vec![Visit::New; graph.colors.len()];
+    let mut path: Vec<usize> = vec![];
+    for id in 0..graph.colors.len() {
+        find_cycles(&graph, id, &mut states, &mut path, &mut report.cycles);
+    }
+    if let Some(root) = root.and_then(|root| graph.lookup(root)) {
+        let mut related = graph.reachable(root, true);
+        related.extend(graph.reachable(root, false));
+        related.insert(root);
+        let mut unreachable: Vec<String> = rules.iter()
+            .filter(|rule| !related.contains(&graph.id(&rule.color)))
+            .map(|rule| rule.color.clone())
+            .collect();
+        unreachable.sort();
+        unreachable.dedup();
+        report.unreachable = unreachable;
+    }
+    report
+}
+
+#[derive(Clone, PartialEq)]
+enum Visit {
+    New,
+    Active,
+    Done
+}
+
+fn find_cycles(graph: &BagGraph, id: usize, states: &mut Vec<Visit>, path: &mut Vec<usize>, cycles: &mut Vec<Vec<String>>) {
+    match states[id] {
+        Visit::Done => return,
+        Visit::Active => {
+            let start = path.iter().position(|p| *p == id).expect("Active bag must be on path");
+            let mut cycle: Vec<String> = path[start..].iter().map(|p| graph.colors[*p].clone()).collect();
+            cycle.push(graph.colors[id].clone());
+            cycles.push(cycle);
+            return;
+        },
+        Visit::New => ()
+    }
+    states[id] = Visit::Active;
+    path.push(id);
+    for (_count, bag) in &graph.contents[id] {
+        find_cycles(graph, *bag, states, path, cycles);
+    }
+    path.pop();
+    states[id] = Visit::Done;
+}
+
+fn checked_graph(rules: &[BagRule]) -> Result<BagGraph, RuleReport> {
+    let report = validate(rules, None);
+    if report.is_valid() {
+        Ok(BagGraph::new(rules))
+    } else {
+        Err(report)
+    }
+}
+
+#[derive(Clone, Copy)]
+enum GraphFormat {
+    Dot,
+    Mermaid
+}
+
+struct ExportOptions {
+    format: GraphFormat,
+    from: Option<String>,
+    to: Option<String>,
+    highlight: Option<String>
+}
+
+fn export(rules: &[BagRule], options: &ExportOptions) -> Result<String, String> {
+    let graph = BagGraph::new(rules);
+    let resolve = |color: &Option<String>| match color {
+        Some(color) => graph.lookup(color).map(Some).ok_or_else(|| format!("Unknown color: {:?}", color)),
+        None => Ok(None)
+    };
+    let (from, to, highlight) = (resolve(&options.from)?, resolve(&options.to)?, resolve(&options.highlight)?);
+    let mut included: HashSet<usize> = (0..graph.colors.len()).collect();
+    if let Some(from) = from {
+        let mut below = graph.reachable(from, false);
+        below.insert(from);
+        included = included.intersection(&below).cloned().collect();
+    }
+    if let Some(to) = to {
+        let mut above = graph.reachable(to, true);
+        above.insert(to);
+        included = included.intersection(&above).cloned().collect();
+    }
+    let mut nodes: Vec<usize> = included.iter().cloned().collect();
+    nodes.sort_unstable();
+    let mut output = String::new();
+    match options.format {
+        GraphFormat::Dot => {
+            output.push_str("digraph bags {\n");
+            for node in &nodes {
+                if Some(*node) == highlight {
+                    output.push_str(&format!("    {:?} [style=filled, fillcolor=gold];\n", graph.colors[*node]));
+                } else {
+                    output.push_str(&format!("    {:?};\n", graph.colors[*node]));
+                }
+            }
+            for node in &nodes {
+                for (count, bag) in &graph.contents[*node] {
+                    if included.contains(bag) {
+                        output.push_str(&format!("    {:?} -> {:?} [label=\"{}\"];\n", graph.colors[*node], graph.colors[*bag], count));
+                    }
+                }
+            }
+            output.push_str("}\n");
+        },
+        GraphFormat::Mermaid => {
+            output.push_str("graph LR\n");
+            for node in &nodes {
+                output.push_str(&format!("    bag{}[\"{}\"]\n", node, graph.colors[*node]));
+            }
+            for node in &nodes {
+                for (count, bag) in &graph.contents[*node] {
+                    if included.contains(bag) {
+                        output.push_str(&format!("    bag{} -->|{}| bag{}\n", node, count, bag));
+                    }
+                }
+            }
+            if let Some(node) = highlight.filter(|node| included.contains(node)) {
+                output.push_str(&format!("    style bag{} fill:#ffd700,stroke:#333\n", node));
+            }
+        }
+    }
+    Ok(output)
 }
 
 pub struct Day7 {}
@@ -83,41 +343,87 @@ impl Day for Day7 {
     }
 
     fn part1(input: &Vec<BagRule>) -> usize {
-        let target = String::from("shiny gold");
-        let trees = build_trees(input);
-        let filtered: Vec<Bag> = trees.into_iter().filter(|b| b.contains(&target)).collect();
-        filtered.len()
+        let graph = checked_graph(input).unwrap_or_else(|report| panic!("Invalid bag rules:\n{}", report));
+        graph.holders_of(TARGET).expect("Target color must be present").len()
     }
 
     fn part2(input: &Vec<BagRule>) -> usize {
-        let target = String::from("shiny gold");
-        let trees = build_trees(input);
-        let filtered: Vec<Bag> = trees.into_iter().filter(|b| b.color == target).collect();
-        let tree = filtered.get(0).expect("Bag should be present");
-        tree.bag_size() - 1
+        let graph = checked_graph(input).unwrap_or_else(|report| panic!("Invalid bag rules:\n{}", report));
+        graph.total_inside(TARGET).expect("Target color must be present")
     }
-}
 
-fn build_tree(rule: &BagRule, lookup: &HashMap<String, &BagRule>) -> Bag {
-    let mut bags: Vec<(u32, Bag)> = vec![];
-    for (count, color) in &rule.contains {
-        bags.push((*count, build_tree(lookup.get(color).expect("Bag must exist"), lookup)))
-    }
-    Bag {
-        color: rule.color.clone(),
-        contents: bags
+    fn command(name: &str, args: &[String]) {
+        let rules = Self::read();
+        if name == "validate" {
+            let root = flag_value(args, "--root");
+            if let Some(root) = root.as_ref().filter(|root| BagGraph::new(&rules).lookup(root).is_none()) {
+                println!("Unknown color: {:?}", root);
+                return;
+            }
+            let report = validate(&rules, root.as_deref());
+            print!("{}", report);
+            println!("{}", if report.is_valid() { "Rules are valid" } else { "Rules are invalid" });
+            return;
+        }
+        if name == "export" {
+            let format = match args.first().map(|a| a.as_str()) {
+                Some("dot") => GraphFormat::Dot,
+                Some("mermaid") => GraphFormat::Mermaid,
+                format => panic!("Unknown export format: {:?}", format)
+            };
+            let options = ExportOptions {
+                format,
+                from: flag_value(args, "--from"),
+                to: flag_value(args, "--to"),
+                highlight: flag_value(args, "--highlight")
+            };
+            match (export(&rules, &options), flag_value(args, "--output")) {
+                (Ok(output), Some(path)) => std::fs::write(path, output).expect("Output file must be writable"),
+                (Ok(output), None) => print!("{}", output),
+                (Err(message), _) => println!("{}", message)
+            }
+            return;
+        }
+        let graph = match checked_graph(&rules) {
+            Ok(graph) => graph,
+            Err(report) => {
+                print!("{}", report);
+                return;
+            }
+        };
+        match name {
+            "holders" => {
+                let color = color_from(args, "--color");
+                match graph.holders_of(&color) {
+                    Some(holders) => {
+                        for holder in &holders {
+                            println!("{}", holder);
+                        }
+                        println!("{:?} bags can hold {:?}", holders.len(), color);
+                    },
+                    None => println!("Unknown color: {:?}", color)
+                }
+            },
+            "inside" => {
+                let color = color_from(args, "--color");
+                match graph.total_inside(&color) {
+                    Some(total) => println!("{:?} bags inside {:?}", total, color),
+                    None => println!("Unknown color: {:?}", color)
+                }
+            },
+            "can-contain" => {
+                let outer = color_from(args, "--outer");
+                let inner = color_from(args, "--inner");
+                match graph.can_contain(&outer, &inner) {
+                    Some(result) => println!("{:?}", result),
+                    None => println!("Unknown color: {:?} or {:?}", outer, inner)
+                }
+            },
+            _ => println!("Unknown command: {:?}", name)
+        }
     }
 }
 
-fn build_trees(rules: &Vec<BagRule>) -> Vec<Bag> {
-    let mut lookup: HashMap<String, &BagRule> = HashMap::new();
-    for rule in rules {
-        lookup.insert(rule.color.clone(), rule);
-    }
-    let mut trees: Vec<Bag> = Vec::new();
-    for rule in rules {
-        let bag = build_tree(&rule, &lookup);
-        trees.push(bag);
-    }
-    trees
+fn color_from(args: &[String], flag: &str) -> String {
+    flag_value(args, flag).unwrap_or_else(|| String::from(TARGET))
 }
\ No newline at end of file
@@ -1,102 +1,612 @@
-use std::fs::File;
-use std::io::{BufRead, BufReader};
-use std::collections::HashSet;
-use either::*;
+use std::fmt;
+use std::fs;
+use std::io::{BufRead, Write};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::rc::Rc;
 use super::Day;
 
 #[derive(Debug, Clone)]
 pub enum Instruction {
     NOP(i32),
     ACC(i32),
-    JMP(i32)
+    JMP(i32),
+    Ext(Rc<dyn Opcode>, Vec<Operand>)
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Instruction::NOP(value) => write!(f, "nop {:+}", value),
+            Instruction::ACC(value) => write!(f, "acc {:+}", value),
+            Instruction::JMP(value) => write!(f, "jmp {:+}", value),
+            Instruction::Ext(opcode, operands) => {
+                write!(f, "{}", opcode.mnemonic())?;
+                for (i, operand) in operands.iter().enumerate() {
+                    write!(f, "{}{}", if i == 0 { " " } else { ", " }, operand)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Operand {
+    Value(i32),
+    Register(String)
+}
+
+impl fmt::Display for Operand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Operand::Value(value) => write!(f, "{:+}", value),
+            Operand::Register(name) => write!(f, "{}", name)
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Registers {
+    pc: i64,
+    acc: i32,
+    named: HashMap<String, i32>
+}
+
+impl Registers {
+    fn get(&self, name: &str) -> Option<i64> {
+        match name {
+            "pc" => Some(self.pc),
+            "acc" => Some(self.acc as i64),
+            _ => self.named.get(name).map(|value| *value as i64)
+        }
+    }
+
+    fn value(&self, operand: &Operand) -> i32 {
+        match operand {
+            Operand::Value(value) => *value,
+            Operand::Register(name) if name == "acc" => self.acc,
+            Operand::Register(name) => *self.named.get(name).unwrap_or(&0)
+        }
+    }
+
+    fn set(&mut self, name: &str, value: i32) {
+        if name == "acc" {
+            self.acc = value;
+        } else {
+            self.named.insert(name.to_string(), value);
+        }
+    }
+
+    fn jump(&mut self, offset: i32) {
+        self.pc += offset as i64;
+    }
+}
+
+pub trait Opcode: fmt::Debug {
+    fn mnemonic(&self) -> &'static str;
+    fn arity(&self) -> usize;
+    fn jump_operand(&self) -> Option<usize> {
+        None
+    }
+    fn execute(&self, operands: &[Operand], registers: &mut Registers);
+}
+
+#[derive(Debug)]
+struct Mul;
+
+impl Opcode for Mul {
+    fn mnemonic(&self) -> &'static str {
+        "mul"
+    }
+    fn arity(&self) -> usize {
+        1
+    }
+    fn execute(&self, operands: &[Operand], registers: &mut Registers) {
+        registers.acc = registers.acc.wrapping_mul(registers.value(&operands[0]));
+        registers.pc += 1;
+    }
+}
+
+#[derive(Debug)]
+struct Mov;
+
+impl Opcode for Mov {
+    fn mnemonic(&self) -> &'static str {
+        "mov"
+    }
+    fn arity(&self) -> usize {
+        2
+    }
+    fn execute(&self, operands: &[Operand], registers: &mut Registers) {
+        let value = registers.value(&operands[1]);
+        match &operands[0] {
+            Operand::Register(name) => registers.set(name, value),
+            Operand::Value(_) => panic!("mov target must be a register")
+        }
+        registers.pc += 1;
+    }
+}
+
+#[derive(Debug)]
+struct Jnz;
+
+impl Opcode for Jnz {
+    fn mnemonic(&self) -> &'static str {
+        "jnz"
+    }
+    fn arity(&self) -> usize {
+        2
+    }
+    fn jump_operand(&self) -> Option<usize> {
+        Some(1)
+    }
+    fn execute(&self, operands: &[Operand], registers: &mut Registers) {
+        if registers.value(&operands[0]) != 0 {
+            registers.jump(registers.value(&operands[1]));
+        } else {
+            registers.pc += 1;
+        }
+    }
+}
+
+pub struct InstructionSet {
+    extensions: HashMap<&'static str, Rc<dyn Opcode>>
+}
+
+impl InstructionSet {
+    fn standard() -> InstructionSet {
+        InstructionSet { extensions: HashMap::new() }
+    }
+
+    fn extended() -> InstructionSet {
+        let mut set = InstructionSet::standard();
+        set.register(Rc::new(Mul));
+        set.register(Rc::new(Mov));
+        set.register(Rc::new(Jnz));
+        set
+    }
+
+    fn register(&mut self, opcode: Rc<dyn Opcode>) {
+        self.extensions.insert(opcode.mnemonic(), opcode);
+    }
+}
+
+#[derive(Debug)]
+pub struct AssemblyError {
+    line: usize,
+    message: String
+}
+
+impl fmt::Display for AssemblyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+fn strip_comment(line: &str) -> &str {
+    match line.find(['#', ';']) {
+        Some(index) => &line[..index],
+        None => line
+    }
+}
+
+fn split_label(line: &str) -> (Option<&str>, &str) {
+    match line.find(':') {
+        Some(index) => (Some(line[..index].trim()), line[index + 1..].trim()),
+        None => (None, line.trim())
+    }
+}
+
+fn assemble(source: &str, set: &InstructionSet) -> Result<Vec<Instruction>, AssemblyError> {
+    let mut labels: HashMap<&str, usize> = HashMap::new();
+    let mut pc = 0;
+    for (index, line) in source.lines().enumerate() {
+        let (label, rest) = split_label(strip_comment(line));
+        if let Some(label) = label {
+            if labels.insert(label, pc).is_some() {
+                return Err(AssemblyError { line: index + 1, message: format!("Duplicate label {:?}", label) });
+            }
+        }
+        if !rest.is_empty() {
+            pc += 1;
+        }
+    }
+    let mut program: Vec<Instruction> = vec![];
+    for (index, line) in source.lines().enumerate() {
+        let error = |message: String| AssemblyError { line: index + 1, message };
+        let (_label, rest) = split_label(strip_comment(line));
+        if rest.is_empty() {
+            continue;
+        }
+        let pc = program.len() as i32;
+        let mut words = rest.split(|c: char| c.is_whitespace() || c == ',').filter(|w| !w.is_empty());
+        let mnemonic = words.next().expect("Mnemonic must be present");
+        let operands: Vec<Operand> = words.map(|word| {
+            if let Ok(value) = word.parse::<i32>() {
+                Operand::Value(value)
+            } else if let Some(target) = labels.get(word) {
+                Operand::Value(*target as i32 - pc)
+            } else {
+                Operand::Register(word.to_string())
+            }
+        }).collect();
+        let instruction = match mnemonic {
+            "nop" | "acc" | "jmp" => {
+                let value = match operands.as_slice() {
+                    [Operand::Value(value)] => *value,
+                    _ => return Err(error(format!("{} takes one integer or label operand", mnemonic)))
+                };
+                match mnemonic {
+                    "nop" => Instruction::NOP(value),
+                    "acc" => Instruction::ACC(value),
+                    _ => Instruction::JMP(value)
+                }
+            },
+            _ => {
+                let opcode = set.extensions.get(mnemonic).ok_or_else(|| error(format!("Unexpected opcode {:?}", mnemonic)))?;
+                if operands.len() != opcode.arity() {
+                    return Err(error(format!("{} takes {} operands", mnemonic, opcode.arity())));
+                }
+                Instruction::Ext(opcode.clone(), operands)
+            }
+        };
+        program.push(instruction);
+    }
+    Ok(program)
+}
+
+fn jump_target(pc: usize, instruction: &Instruction) -> Option<i32> {
+    match instruction {
+        Instruction::JMP(value) => Some(pc as i32 + value),
+        Instruction::Ext(opcode, operands) => {
+            match opcode.jump_operand().map(|i| &operands[i]) {
+                Some(Operand::Value(value)) => Some(pc as i32 + value),
+                _ => None
+            }
+        },
+        _ => None
+    }
+}
+
+fn disassemble(program: &[Instruction]) -> String {
+    let targets: HashSet<usize> = program.iter().enumerate()
+        .filter_map(|(pc, instruction)| jump_target(pc, instruction))
+        .filter(|target| *target >= 0 && *target as usize <= program.len())
+        .map(|target| target as usize)
+        .collect();
+    let mut output = String::new();
+    for (pc, instruction) in program.iter().enumerate() {
+        if targets.contains(&pc) {
+            output.push_str(&format!("L{}:\n", pc));
+        }
+        let text = match (instruction, jump_target(pc, instruction)) {
+            (Instruction::JMP(_), Some(target)) if targets.contains(&(target as usize)) => format!("jmp L{}", target),
+            (Instruction::Ext(opcode, operands), Some(target)) if targets.contains(&(target as usize)) => {
+                let index = opcode.jump_operand().expect("Jump operand must exist");
+                let operands: Vec<String> = operands.iter().enumerate().map(|(i, operand)| {
+                    if i == index { format!("L{}", target) } else { operand.to_string() }
+                }).collect();
+                format!("{} {}", opcode.mnemonic(), operands.join(", "))
+            },
+            _ => instruction.to_string()
+        };
+        output.push_str(&format!("    {}\n", text));
+    }
+    if targets.contains(&program.len()) {
+        output.push_str(&format!("L{}:\n", program.len()));
+    }
+    output
+}
+
+pub enum Answer {
+    Acc(i32),
+    NoRepair
+}
+
+impl fmt::Debug for Answer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Answer::Acc(acc) => write!(f, "{}", acc),
+            Answer::NoRepair => write!(f, "No single-instruction repair exists")
+        }
+    }
+}
+
+struct ControlFlowGraph {
+    predecessors: Vec<Vec<usize>>
+}
+
+impl ControlFlowGraph {
+    fn new(program: &[Instruction]) -> ControlFlowGraph {
+        let end = program.len();
+        let mut predecessors: Vec<Vec<usize>> = vec![vec![]; end + 1];
+        for (pc, instruction) in program.iter().enumerate() {
+            let mut successors = vec![];
+            match instruction {
+                Instruction::JMP(_) => (),
+                Instruction::Ext(opcode, _) if opcode.jump_operand().is_some() => successors.push(pc as i32 + 1),
+                _ => successors.push(pc as i32 + 1)
+            }
+            successors.extend(jump_target(pc, instruction));
+            for next in successors {
+                if next >= 0 && next as usize <= end {
+                    predecessors[next as usize].push(pc);
+                }
+            }
+        }
+        ControlFlowGraph { predecessors }
+    }
+
+    fn terminating(&self) -> HashSet<usize> {
+        let end = self.predecessors.len() - 1;
+        let mut reached: HashSet<usize> = HashSet::new();
+        let mut pending = vec![end];
+        reached.insert(end);
+        while let Some(pc) = pending.pop() {
+            for previous in &self.predecessors[pc] {
+                if reached.insert(*previous) {
+                    pending.push(*previous);
+                }
+            }
+        }
+        reached
+    }
+}
+
+#[derive(Debug)]
+pub struct Repair {
+    pc: usize,
+    original: Instruction,
+    replacement: Instruction
+}
+
+impl Repair {
+    fn apply(&self, program: &[Instruction]) -> Vec<Instruction> {
+        let mut repaired = program.to_vec();
+        repaired[self.pc] = self.replacement.clone();
+        repaired
+    }
+}
+
+fn repairs(program: &[Instruction]) -> Vec<Repair> {
+    let terminating = ControlFlowGraph::new(program).terminating();
+    let mut machine = Machine::new(program);
+    machine.run_until(&HashSet::new());
+    let mut repairs = vec![];
+    for (pc, instruction, _acc) in &machine.trace {
+        let (replacement, next) = match instruction {
+            Instruction::NOP(value) => (Instruction::JMP(*value), *pc as i32 + value),
+            Instruction::JMP(value) => (Instruction::NOP(*value), *pc as i32 + 1),
+            _ => continue
+        };
+        if next >= 0 && terminating.contains(&(next as usize)) {
+            repairs.push(Repair { pc: *pc, original: instruction.clone(), replacement });
+        }
+    }
+    repairs.sort_by_key(|repair| repair.pc);
+    repairs
 }
 
 pub struct Day8 {}
 
 impl Day for Day8 { 
     type Input = Vec<Instruction>;
-    type Output = i32;
+    type Output = Answer;
 
     fn read() -> Vec<Instruction> {
-        let mut data: Vec<Instruction> = vec![];
-        let file = File::open("./src/day8/input").expect("Input file must exist");
-        for line in BufReader::new(file).lines() {
-            let line = line.expect("Line must be present");
-            let parts: Vec<&str> = line.split(' ').collect();
-            let opcode = parts.get(0).expect("Opcode must be present");
-            let value = parts.get(1).expect("Value must be present").parse::<i32>().expect("Value must be integer");
-            let instruction = match *opcode {
-                "nop" => Instruction::NOP(value),
-                "acc" => Instruction::ACC(value),
-                "jmp" => Instruction::JMP(value),
-                _ => panic!("Unexpected opcode {:?}", opcode)
-            };
-            data.push(instruction);
-        }
-        data
-    }
-
-    fn part1(input: &Vec<Instruction>) -> i32 {
+        let source = fs::read_to_string("./src/day8/input").expect("Input file must exist");
+        assemble(&source, &InstructionSet::standard()).unwrap_or_else(|e| panic!("Invalid program: {}", e))
+    }
+
+    fn part1(input: &Vec<Instruction>) -> Answer {
         let result = run(input);
         match result {
-            Either::Left(acc) => return acc,
-            Either::Right(acc) => panic!("Program completed successfully. That shouldn't happen! ACC: {:?}", acc)
-        }
-    }
-
-    fn part2(input: &Vec<Instruction>) -> i32 {
-        let program = input;
-        let last_index = input.len() - 1;
-        for pc in 0..=last_index {
-            match input[pc] {
-                Instruction::NOP(value) => {
-                    let program = program.clone().into_iter().enumerate().map(|(i, o)| {
-                        if i == pc {
-                            Instruction::JMP(value)
-                        } else {
-                            o
-                        }
-                    }).collect();
-                    if let Either::Right(acc) = run(&program) {
-                        return acc
+            RunOutcome::InfiniteLoop { acc, .. } => Answer::Acc(acc),
+            outcome => panic!("Program did not loop. That shouldn't happen! {:?}", outcome)
+        }
+    }
+
+    fn part2(input: &Vec<Instruction>) -> Answer {
+        match repairs(input).first() {
+            Some(repair) => match run(&repair.apply(input)) {
+                RunOutcome::Terminated { acc } => Answer::Acc(acc),
+                outcome => panic!("Repair at pc {} does not terminate: {:?}", repair.pc, outcome)
+            },
+            None => Answer::NoRepair
+        }
+    }
+
+    fn command(name: &str, args: &[String]) {
+        let program = match args.first() {
+            Some(path) => {
+                let source = fs::read_to_string(path).expect("Program file must exist");
+                match assemble(&source, &InstructionSet::extended()) {
+                    Ok(program) => program,
+                    Err(e) => {
+                        println!("{}", e);
+                        return;
                     }
-                },
-                Instruction::JMP(value) => {
-                    let program = program.clone().into_iter().enumerate().map(|(i, o)| {
-                        if i == pc {
-                            Instruction::NOP(value)
-                        } else {
-                            o
-                        }
-                    }).collect();
-                    if let Either::Right(acc) = run(&program) {
-                        return acc
+                }
+            },
+            None => Self::read()
+        };
+        match name {
+            "debug" => debug(&program),
+            "repairs" => {
+                let repairs = repairs(&program);
+                if repairs.is_empty() {
+                    println!("No single-instruction repair exists");
+                }
+                for repair in repairs {
+                    println!("pc {}: {} -> {}", repair.pc, repair.original, repair.replacement);
+                }
+            },
+            "disassemble" => print!("{}", disassemble(&program)),
+            "run" => {
+                println!("{:?}", run(&program));
+            },
+            _ => println!("Unknown command: {:?}", name)
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum RunOutcome {
+    Terminated { acc: i32 },
+    InfiniteLoop { acc: i32, loop_start_pc: usize, cycle: Vec<usize> },
+    OutOfBounds { pc: i64, acc: i32 },
+    StepLimit { steps: usize, acc: i32 }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Status {
+    Running,
+    Breakpoint,
+    Stopped(RunOutcome)
+}
+
+const STEP_LIMIT: usize = 10_000_000;
+const TRACE_LIMIT: usize = 1_000;
+
+pub struct Machine<'a> {
+    program: &'a [Instruction],
+    registers: Registers,
+    visited: HashSet<usize>,
+    detect_loops: bool,
+    step_limit: usize,
+    steps: usize,
+    trace_limit: usize,
+    trace: VecDeque<(usize, Instruction, i32)>
+}
+
+impl<'a> Machine<'a> {
+    fn new(program: &'a [Instruction]) -> Machine<'a> {
+        let detect_loops = !program.iter().any(|instruction| match instruction {
+            Instruction::Ext(opcode, _) => opcode.jump_operand().is_some(),
+            _ => false
+        });
+        Machine {
+            program,
+            registers: Registers::default(),
+            visited: HashSet::new(),
+            detect_loops,
+            step_limit: STEP_LIMIT,
+            steps: 0,
+            trace_limit: TRACE_LIMIT.max(program.len()),
+            trace: VecDeque::new()
+        }
+    }
+
+    fn step(&mut self) -> Status {
+        let acc = self.registers.acc;
+        let pc = self.registers.pc;
+        if pc == self.program.len() as i64 {
+            return Status::Stopped(RunOutcome::Terminated { acc });
+        }
+        if pc < 0 || pc > self.program.len() as i64 {
+            return Status::Stopped(RunOutcome::OutOfBounds { pc, acc });
+        }
+        let pc = pc as usize;
+        if self.detect_loops && !self.visited.insert(pc) {
+            let start = self.trace.iter().position(|(p, _, _)| *p == pc).expect("Visited pc must be traced");
+            let cycle = self.trace.iter().skip(start).map(|(p, _, _)| *p).collect();
+            return Status::Stopped(RunOutcome::InfiniteLoop { acc, loop_start_pc: pc, cycle });
+        }
+        if self.steps >= self.step_limit {
+            return Status::Stopped(RunOutcome::StepLimit { steps: self.steps, acc });
+        }
+        let instruction = self.program[pc].clone();
+        match &instruction {
+            Instruction::NOP(_) => self.registers.pc += 1,
+            Instruction::ACC(value) => {
+                self.registers.acc = self.registers.acc.wrapping_add(*value);
+                self.registers.pc += 1;
+            },
+            Instruction::JMP(value) => self.registers.jump(*value),
+            Instruction::Ext(opcode, operands) => opcode.execute(operands, &mut self.registers)
+        }
+        if self.trace.len() == self.trace_limit {
+            self.trace.pop_front();
+        }
+        self.trace.push_back((pc, instruction, self.registers.acc));
+        self.steps += 1;
+        Status::Running
+    }
+
+    fn run_until(&mut self, breakpoints: &HashSet<i64>) -> Status {
+        loop {
+            match self.step() {
+                Status::Running => {
+                    if breakpoints.contains(&self.registers.pc) {
+                        return Status::Breakpoint;
                     }
                 },
-                _ => ()
+                status => return status
             }
         }
-        panic!("No value found!");
+    }
+
+    fn run(&mut self) -> RunOutcome {
+        match self.run_until(&HashSet::new()) {
+            Status::Stopped(outcome) => outcome,
+            status => panic!("Run stopped unexpectedly: {:?}", status)
+        }
     }
 }
 
-fn run(program: &Vec<Instruction>) -> Either<i32, i32> {
-    let mut pc = 0;
-    let mut cache: HashSet<usize> = HashSet::new();
-    let mut acc: i32 = 0;
-    while pc < program.len() {
-        if cache.contains(&pc) {
-            return Either::Left(acc)
-        }
-        cache.insert(pc);
-        match program[pc] {
-            Instruction::NOP(_) => pc += 1,
-            Instruction::ACC(value) => { 
-                acc += value;
-                pc += 1;
+fn run(program: &[Instruction]) -> RunOutcome {
+    Machine::new(program).run()
+}
+
+fn debug(program: &[Instruction]) {
+    let mut machine = Machine::new(program);
+    let mut breakpoints: HashSet<i64> = HashSet::new();
+    let stdin = std::io::stdin();
+    print!("(day8) ");
+    std::io::stdout().flush().expect("Stdout must flush");
+    for line in stdin.lock().lines() {
+        let line = line.expect("Line must be present");
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words.as_slice() {
+            ["step"] | ["s"] => {
+                match machine.step() {
+                    Status::Running => {
+                        let (pc, instruction, acc) = machine.trace.back().expect("Step must be traced");
+                        println!("{:>5}: {} acc={}", pc, instruction, acc);
+                    },
+                    status => println!("{:?} at pc {}", status, machine.registers.pc)
+                }
+            },
+            ["break", pc] | ["b", pc] => {
+                match pc.parse::<usize>() {
+                    Ok(pc) => {
+                        breakpoints.insert(pc as i64);
+                        println!("Breakpoint set at pc {}", pc);
+                    },
+                    Err(_) => println!("Invalid pc: {:?}", pc)
+                }
+            },
+            ["print", register] | ["p", register] => {
+                match machine.registers.get(register) {
+                    Some(value) => println!("{} = {}", register, value),
+                    None => println!("Unknown register: {:?}", register)
+                }
+            },
+            ["continue"] | ["c"] => {
+                let status = machine.run_until(&breakpoints);
+                println!("{:?} at pc {} acc={}", status, machine.registers.pc, machine.registers.acc);
+            },
+            ["trace"] => {
+                for (pc, instruction, acc) in &machine.trace {
+                    println!("{:>5}: {} acc={}", pc, instruction, acc);
+                }
             },
-            Instruction::JMP(value) => pc = ((pc as i32) + value) as usize
+            ["quit"] | ["q"] => break,
+            [] => (),
+            _ => println!("Unknown command: {:?}", line)
         }
+        print!("(day8) ");
+        std::io::stdout().flush().expect("Stdout must flush");
     }
-    Either::Right(acc)
+    println!();
 }
\ No newline at end of file
@@ -1,7 +1,166 @@
-use itertools::Itertools;
 use std::fs::File;
-use std::io::{BufRead, BufReader};
-use super::Day;
+use std::io::{BufRead, BufReader, Write};
+use std::collections::{HashMap, VecDeque};
+use super::{Day, flag_value};
+
+const PREAMBLE_SIZE: usize = 25;
+const STREAM_BUFFER: usize = 100_000;
+
+struct WindowSums {
+    size: usize,
+    window: VecDeque<u64>,
+    sums: HashMap<u64, usize>
+}
+
+impl WindowSums {
+    fn new(size: usize) -> WindowSums {
+        WindowSums { size, window: VecDeque::with_capacity(size), sums: HashMap::new() }
+    }
+
+    fn is_full(&self) -> bool {
+        self.window.len() == self.size
+    }
+
+    fn contains(&self, sum: u64) -> bool {
+        self.sums.contains_key(&sum)
+    }
+
+    fn push(&mut self, value: u64) {
+        if self.is_full() {
+            let outgoing = self.window.pop_front().expect("Window cannot be empty");
+            for other in &self.window {
+                let sum = outgoing + other;
+                let count = self.sums.get_mut(&sum).expect("Sum must be tracked");
+                *count -= 1;
+                if *count == 0 {
+                    self.sums.remove(&sum);
+                }
+            }
+        }
+        for other in &self.window {
+            *self.sums.entry(value + other).or_insert(0) += 1;
+        }
+        self.window.push_back(value);
+    }
+}
+
+pub struct StreamingValidator<I: Iterator<Item = u64>> {
+    values: I,
+    sums: WindowSums,
+    index: usize
+}
+
+impl<I: Iterator<Item = u64>> StreamingValidator<I> {
+    fn new(values: I, preamble_size: usize) -> StreamingValidator<I> {
+        StreamingValidator { values, sums: WindowSums::new(preamble_size), index: 0 }
+    }
+}
+
+impl<I: Iterator<Item = u64>> Iterator for StreamingValidator<I> {
+    type Item = (usize, u64);
+
+    fn next(&mut self) -> Option<(usize, u64)> {
+        for value in &mut self.values {
+            let index = self.index;
+            let invalid = self.sums.is_full() && !self.sums.contains(value);
+            self.sums.push(value);
+            self.index += 1;
+            if invalid {
+                return Some((index, value));
+            }
+        }
+        None
+    }
+}
+
+fn stream_weakness<I: Iterator<Item = u64>>(values: I, target: u64) -> Option<u64> {
+    let mut range: VecDeque<u64> = VecDeque::new();
+    let mut sum = 0;
+    for value in values {
+        range.push_back(value);
+        sum += value;
+        while range.len() > 1 && sum > target {
+            sum -= range.pop_front().expect("Range cannot be empty");
+        }
+        if range.len() > 1 && sum == target {
+            let min = range.iter().min().expect("Range cannot be empty");
+            let max = range.iter().max().expect("Range cannot be empty");
+            return Some(min + max);
+        }
+    }
+    None
+}
+
+fn values_from<R: BufRead>(reader: R) -> impl Iterator<Item = u64> {
+    reader.lines().enumerate().filter_map(|(index, line)| {
+        let line = line.expect("Line must be present");
+        let line = line.trim();
+        if line.is_empty() {
+            return None;
+        }
+        match line.parse::<u64>() {
+            Ok(value) => Some(value),
+            Err(_) => {
+                eprintln!("Skipping line {}: {:?}", index + 1, line);
+                None
+            }
+        }
+    })
+}
+
+fn open_stream(path: &str) -> Box<dyn BufRead> {
+    if path == "-" {
+        Box::new(BufReader::new(std::io::stdin()))
+    } else {
+        Box::new(BufReader::new(File::open(path).expect("Input file must exist")))
+    }
+}
+
+pub struct XmasCipher<'a> {
+    numbers: &'a [u64],
+    preamble_size: usize
+}
+
+impl<'a> XmasCipher<'a> {
+    fn new(numbers: &'a [u64], preamble_size: usize) -> XmasCipher<'a> {
+        XmasCipher { numbers, preamble_size }
+    }
+
+    fn invalid_numbers(&self) -> Vec<(usize, u64)> {
+        StreamingValidator::new(self.numbers.iter().cloned(), self.preamble_size).collect()
+    }
+
+    fn contiguous_range(&self, target: u64) -> Option<&'a [u64]> {
+        let mut prefix: Vec<u64> = vec![0];
+        for value in self.numbers {
+            prefix.push(prefix[prefix.len() - 1] + value);
+        }
+        let mut start = 0;
+        for end in 0..self.numbers.len() {
+            while start < end && prefix[end + 1] - prefix[start] > target {
+                start += 1;
+            }
+            if end > start && prefix[end + 1] - prefix[start] == target {
+                return Some(&self.numbers[start..=end]);
+            }
+        }
+        None
+    }
+
+    fn weakness(&self, target: u64) -> Option<u64> {
+        self.contiguous_range(target).map(|range| {
+            let min = range.iter().min().expect("Range cannot be empty");
+            let max = range.iter().max().expect("Range cannot be empty");
+            min + max
+        })
+    }
+}
+
+fn preamble_from(args: &[String]) -> usize {
+    let size = flag_value(args, "--preamble").map(|a| a.parse::<usize>().expect("Preamble size must be integer")).unwrap_or(PREAMBLE_SIZE);
+    assert!(size > 0, "Preamble size must be positive");
+    size
+}
 
 pub struct Day9 {}
 
@@ -21,42 +180,86 @@ impl Day for Day9 {
     }
 
     fn part1(input: &Vec<u64>) -> u64 {
-        let preamble_size = 25;
-        let first_index = preamble_size;
-        let last_index = input.len() - 1;
-        for i in first_index..=last_index {
-            let value = input[i];
-            let end = i - 1;
-            let start = end - (preamble_size - 1);
-            let mut sum_exists = false;
-            for pair in input[start..=end].into_iter().combinations(2) {
-                if pair.into_iter().fold(0, |acc, x| acc + x) == value {
-                    sum_exists = true;
-                    break;
-                }
-            }
-            if !sum_exists {
-                return value;
-            }
+        let cipher = XmasCipher::new(input, PREAMBLE_SIZE);
+        match cipher.invalid_numbers().first() {
+            Some((_index, value)) => *value,
+            None => panic!("Entire sequence is valid!")
         }
-        panic!("Entire sequence is valid!");
     }
 
     fn part2(input: &Vec<u64>) -> u64 {
         let target = Self::part1(input);
-        let largest_subset = input.len();
-        for i in 2..=largest_subset {
-            let last_start = input.len() - 1 - i;
-            for j in 0..=last_start {
-                let sum = input[j..=j+i].into_iter().fold(0, |acc, x| acc + x);
-                if sum == target {
-                    let slice: Vec<u64> = input[j..=j+i].into_iter().map(|i| *i).collect();
-                    let min = slice.iter().fold(u64::MAX, |m, x| m.min(*x));
-                    let max = slice.iter().fold(u64::MIN, |m, x| m.max(*x));
-                    return max + min
+        let cipher = XmasCipher::new(input, PREAMBLE_SIZE);
+        cipher.weakness(target).expect("Sum not found!")
+    }
+
+    fn command(name: &str, args: &[String]) {
+        let path = flag_value(args, "--path").unwrap_or_else(|| String::from("./src/day9/input"));
+        match name {
+            "stream" => {
+                let mut first: Option<u64> = None;
+                let buffered = path == "-";
+                let mut recent: VecDeque<u64> = VecDeque::new();
+                let mut dropped = false;
+                let values = values_from(open_stream(&path)).inspect(|value| {
+                    if !buffered {
+                        return;
+                    }
+                    if recent.len() == STREAM_BUFFER {
+                        recent.pop_front();
+                        dropped = true;
+                    }
+                    recent.push_back(*value);
+                });
+                for (index, value) in StreamingValidator::new(values, preamble_from(args)) {
+                    println!("{}: {}", index, value);
+                    std::io::stdout().flush().expect("Stdout must flush");
+                    first = first.or(Some(value));
                 }
-            }
+                if let Some(target) = first {
+                    let weakness = if buffered {
+                        if dropped {
+                            println!("Searching only the last {} values read from stdin", STREAM_BUFFER);
+                        }
+                        stream_weakness(recent.into_iter(), target)
+                    } else {
+                        stream_weakness(values_from(open_stream(&path)), target)
+                    };
+                    match weakness {
+                        Some(weakness) => println!("weakness for {}: {}", target, weakness),
+                        None => println!("No contiguous range sums to {}", target)
+                    }
+                }
+                return;
+            },
+            "stream-weakness" => {
+                let target = flag_value(args, "--target").expect("Target must be present").parse::<u64>().expect("Target must be integer");
+                match stream_weakness(values_from(open_stream(&path)), target) {
+                    Some(weakness) => println!("weakness for {}: {}", target, weakness),
+                    None => println!("No contiguous range sums to {}", target)
+                }
+                return;
+            },
+            _ => ()
+        }
+        let input = Self::read();
+        let cipher = XmasCipher::new(&input, preamble_from(args));
+        match name {
+            "invalid" => {
+                for (index, value) in cipher.invalid_numbers() {
+                    println!("{}: {}", index, value);
+                }
+            },
+            "weakness" => {
+                match cipher.invalid_numbers().first() {
+                    Some((_index, target)) => match cipher.contiguous_range(*target) {
+                        Some(range) => println!("{:?} sums to {}, weakness {:?}", range, target, cipher.weakness(*target)),
+                        None => println!("No contiguous range sums to {}", target)
+                    },
+                    None => println!("Entire sequence is valid!")
+                }
+            },
+            _ => println!("Unknown command: {:?}", name)
         }
-        panic!("Sum not found!");
     }
 }
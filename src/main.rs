@@ -25,6 +25,7 @@ mod day22;
 mod day23;
 mod day24;
 mod day25;
+mod crt;
 
 pub trait Day {
     type Input;
@@ -33,9 +34,24 @@ pub trait Day {
     fn read() -> Self::Input;
     fn part1(input: &Self::Input) -> Self::Output;
     fn part2(input: &Self::Input) -> Self::Output;
+
+    fn command(name: &str, _args: &[String]) {
+        println!("Unknown command: {:?}", name);
+    }
+}
+
+pub fn flag_value(args: &[String], flag: &str) -> Option<String> {
+    args.iter().position(|a| a == flag).map(|i| args.get(i + 1).unwrap_or_else(|| panic!("Value for {} must be present", flag)).clone())
+}
+
+fn perform<D: Day>(mode: &str, args: &[String]) {
+    match mode.parse::<u32>() {
+        Ok(part) => perform_part::<D>(part),
+        Err(_) => D::command(mode, args)
+    }
 }
 
-fn perform<D: Day>(part: u32) {
+fn perform_part<D: Day>(part: u32) {
     let input = D::read();
     match part {
         1 => {
@@ -59,38 +75,37 @@ fn main() {
         .trim()
         .parse::<u32>()
         .expect("Valid integer required");
-    let part = std::env::args()
+    let mode = std::env::args()
         .nth(2)
-        .expect("no path given")
-        .trim()
-        .parse::<u32>()
-        .expect("Valid integer required");
+        .expect("no part or command given");
+    let mode = mode.trim();
+    let args: Vec<String> = std::env::args().skip(3).collect();
     match day {
-        1 => perform::<day1::Day1>(part),
-        2 => perform::<day2::Day2>(part),
-        3 => perform::<day3::Day3>(part),
-        4 => perform::<day4::Day4>(part),
-        5 => perform::<day5::Day5>(part),
-        6 => perform::<day6::Day6>(part),
-        7 => perform::<day7::Day7>(part),
-        8 => perform::<day8::Day8>(part),
-        9 => perform::<day9::Day9>(part),
-        10 => perform::<day10::Day10>(part),
-        11 => perform::<day11::Day11>(part),
-        12 => perform::<day12::Day12>(part),
-        13 => perform::<day13::Day13>(part),
-        14 => perform::<day14::Day14>(part),
-        15 => perform::<day15::Day15>(part),
-        16 => perform::<day16::Day16>(part),
-        17 => perform::<day17::Day17>(part),
-        18 => perform::<day18::Day18>(part),
-        19 => perform::<day19::Day19>(part),
-        20 => perform::<day20::Day20>(part),
-        21 => perform::<day21::Day21>(part),
-        22 => perform::<day22::Day22>(part),
-        23 => perform::<day23::Day23>(part),
-        24 => perform::<day24::Day24>(part),
-        25 => perform::<day25::Day25>(part),
+        1 => perform::<day1::Day1>(mode, &args),
+        2 => perform::<day2::Day2>(mode, &args),
+        3 => perform::<day3::Day3>(mode, &args),
+        4 => perform::<day4::Day4>(mode, &args),
+        5 => perform::<day5::Day5>(mode, &args),
+        6 => perform::<day6::Day6>(mode, &args),
+        7 => perform::<day7::Day7>(mode, &args),
+        8 => perform::<day8::Day8>(mode, &args),
+        9 => perform::<day9::Day9>(mode, &args),
+        10 => perform::<day10::Day10>(mode, &args),
+        11 => perform::<day11::Day11>(mode, &args),
+        12 => perform::<day12::Day12>(mode, &args),
+        13 => perform::<day13::Day13>(mode, &args),
+        14 => perform::<day14::Day14>(mode, &args),
+        15 => perform::<day15::Day15>(mode, &args),
+        16 => perform::<day16::Day16>(mode, &args),
+        17 => perform::<day17::Day17>(mode, &args),
+        18 => perform::<day18::Day18>(mode, &args),
+        19 => perform::<day19::Day19>(mode, &args),
+        20 => perform::<day20::Day20>(mode, &args),
+        21 => perform::<day21::Day21>(mode, &args),
+        22 => perform::<day22::Day22>(mode, &args),
+        23 => perform::<day23::Day23>(mode, &args),
+        24 => perform::<day24::Day24>(mode, &args),
+        25 => perform::<day25::Day25>(mode, &args),
         _ => println!("Unknown day!")
     }
 }